@@ -2,8 +2,10 @@ use std::io::{self, Write};
 
 use epimetheus::dice::{
     self,
+    receipt,
     value::RRVal,
 };
+use epimetheus::table::{self, Tables};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
@@ -15,7 +17,7 @@ async fn main() {
             let rest = line.strip_prefix("sort ").unwrap();
             match dice::eval(rest).await {
                 Ok(val) => {
-                    if let RRVal::Array(mut arr) = val {
+                    if let RRVal::Array(mut arr) = val.value {
                         arr.sort_unstable();
                         println!("{}", RRVal::Array(arr));
                     } else {
@@ -27,13 +29,79 @@ async fn main() {
                     break;
                 }
             };
-        } else {
-            match dice::eval(&line).await {
-                Ok(val) => println!("{val}"),
+        } else if let Some(rest) = line.strip_prefix("dist ") {
+            match dice::distribution(rest).await {
+                Ok(dist) => {
+                    for (v, p) in dist.outcomes() {
+                        println!("{v}\t{p}\t{:.6}", p.to_f64());
+                    }
+                }
+                Err(e) => eprintln!("error: {e}"),
+            }
+        } else if let Some(rest) = line.strip_prefix("receipt ") {
+            print_eval(receipt_eval(rest).await);
+        } else if let Some(rest) = line.strip_prefix("table ") {
+            // the same tables as the bot's `%table`, from `$TABLES_DIR`
+            match table_roll(rest.trim()).await {
+                Ok(res) => {
+                    eprintln!("{} rolled {}", res.table, res.roll);
+                    println!("{}", res.text);
+                }
                 Err(e) => eprintln!("error: {e}"),
             }
+        } else {
+            // a trailing `seed=12345` redoes a roll from the bot
+            let (expr, seed) = match line.rsplit_once(" seed=") {
+                Some((expr, seed)) if seed.parse::<u64>().is_ok() => (expr, seed.parse().ok()),
+                _ => (&line[..], None),
+            };
+            let options = dice::EvalOptions {
+                seed,
+                ..Default::default()
+            };
+            print_eval(dice::eval_with(expr, options).await);
         }
         eprint!("> ");
         io::stderr().flush().unwrap();
     }
 }
+
+/// Redoes a roll from a `%commit` session, given `<secret> <index> <expression>`.
+async fn receipt_eval(args: &str) -> anyhow::Result<dice::Evaluation> {
+    let mut args = args.splitn(3, ' ');
+    let (Some(secret), Some(index), Some(expr)) = (args.next(), args.next(), args.next()) else {
+        anyhow::bail!("usage: receipt <secret> <index> <expression>");
+    };
+    let secret = receipt::parse_secret(secret)?;
+    let index = index
+        .parse()
+        .map_err(|_| anyhow::anyhow!("roll index must be a number"))?;
+    eprintln!(
+        "commitment {}",
+        receipt::Session::from_secret(secret).commitment()
+    );
+    let options = dice::EvalOptions {
+        seed: Some(receipt::roll_seed(&secret, index)),
+        ..Default::default()
+    };
+    dice::eval_with(expr, options).await
+}
+
+async fn table_roll(name: &str) -> anyhow::Result<table::TableRoll> {
+    Tables::load(table::tables_dir()).await?.roll(name, None).await
+}
+
+fn print_eval(res: anyhow::Result<dice::Evaluation>) {
+    match res {
+        Ok(val) => {
+            if !val.rolls.is_empty() {
+                eprintln!("{}", val.breakdown());
+            }
+            if let Some(seed) = val.seed {
+                eprintln!("seed={seed}");
+            }
+            println!("{}", val.value);
+        }
+        Err(e) => eprintln!("error: {e}"),
+    }
+}
@@ -1,15 +1,28 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use serenity::all::{GatewayIntents, Message, MessageReference};
-use serenity::builder::{CreateEmbed, CreateEmbedFooter, CreateMessage};
+use serenity::all::{ChannelId, GatewayIntents, Message, MessageReference};
+use serenity::builder::{CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateMessage};
 use serenity::client::{Context, EventHandler};
 use serenity::Client;
 
-use epimetheus::{dice, os};
+use epimetheus::{
+    chart::Histogram,
+    dice,
+    dice::{deck::Deck, receipt::Session},
+    os,
+    table::{self, Tables},
+};
 
 struct Handler {
     start_time: Instant,
+    /// The channels where `%commit` has been used, and not yet `%reveal`ed.
+    sessions: Mutex<HashMap<ChannelId, Session>>,
+    /// Each channel's deck of cards, for `%draw` and `draw(n)`. A channel gets a shuffled
+    /// standard deck the first time it draws.
+    decks: Mutex<HashMap<ChannelId, Deck>>,
 }
 
 #[cfg(not(debug_assertions))]
@@ -18,6 +31,150 @@ const PREFIX: &str = "%";
 #[cfg(debug_assertions)]
 const PREFIX: &str = "t%";
 
+/// Discord's maximum message length, in characters.
+const MESSAGE_LIMIT: usize = 2000;
+
+/// How many times `%dist` rolls an expression whose distribution can't be worked out exactly.
+const DIST_SAMPLES: usize = 10_000;
+
+/// `%dist` and `%sim` send a PNG chart instead of a text one when there are more outcomes than
+/// this.
+const TEXT_CHART_MAX_BARS: usize = 30;
+
+/// The most times `%sim` will roll an expression.
+const SIM_MAX_RUNS: usize = 100_000;
+
+/// How long `%sim` gets to roll an expression, in total.
+const SIM_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+/// The percentiles listed by `%sim`.
+const SIM_PERCENTILES: [f64; 5] = [5., 25., 50., 75., 95.];
+
+/// `s` with any code formatting around it taken off.
+fn strip_code(s: &str) -> &str {
+    let s = s.trim();
+    if s.starts_with("```") && s.ends_with("```") && s.len() >= 6 {
+        &s[3..s.len() - 3]
+    } else if s.starts_with('`') && s.ends_with('`') && s.len() >= 2 {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+/// The expression given to a command, with any code formatting around it taken off.
+fn expr_arg(content: &str) -> Option<&str> {
+    let (_, expr) = content.split_once(' ')?;
+    Some(strip_code(expr))
+}
+
+/// Splits a trailing `seed=12345` off of a command's arguments.
+fn seed_arg(args: &str) -> (&str, Option<u64>) {
+    let args = args.trim_end();
+    if let Some((rest, last)) = args.rsplit_once(char::is_whitespace) {
+        if let Some(Ok(seed)) = last.strip_prefix("seed=").map(str::parse) {
+            return (rest, Some(seed));
+        }
+    }
+    (args, None)
+}
+
+/// A bold "NAT 20!" or "NAT 1!" to lead a roll's reply with, if any of its kept d20s crit or
+/// fumbled.
+fn crit_marker(v: &dice::Evaluation) -> String {
+    let nat = |die: Option<&dice::value::DieRoll>| {
+        die.map(|d| format!("**NAT {}!** ", d.faces[0]))
+            .unwrap_or_default()
+    };
+    format!("{}{}", nat(v.crits().next()), nat(v.fumbles().next()))
+}
+
+/// `deck`, with every card put back and shuffled.
+fn shuffled(mut deck: Deck) -> Deck {
+    deck.reset(&mut rand::thread_rng());
+    deck
+}
+
+/// How many cards are where in `deck`.
+fn deck_status(deck: &Deck) -> String {
+    format!(
+        "{} cards left to draw, {} drawn, {} in the discard pile.",
+        deck.remaining(),
+        deck.drawn().len(),
+        deck.discards().len()
+    )
+}
+
+/// Lists the cards just drawn, or attaches them as a file if there are too many to fit in a
+/// message.
+fn drawn_message(cards: &[impl AsRef<str>], remaining: usize) -> CreateMessage {
+    let list = cards
+        .iter()
+        .map(|c| inline_code(c.as_ref()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let text = format!("Drew {list}. {remaining} cards left.");
+    if text.chars().count() <= MESSAGE_LIMIT {
+        CreateMessage::new().content(text)
+    } else {
+        let file = cards
+            .iter()
+            .map(|c| format!("{}\n", c.as_ref()))
+            .collect::<String>();
+        CreateMessage::new()
+            .content(format!(
+                "Drew {} cards. {remaining} cards left.",
+                cards.len()
+            ))
+            .add_file(CreateAttachment::bytes(file.into_bytes(), "cards.txt"))
+    }
+}
+
+/// Summarises a simulation of `expr`, with its stats and a chart of how often each outcome came
+/// up.
+fn sim_message(
+    expr: &str,
+    requested: usize,
+    sim: &dice::Simulation,
+) -> anyhow::Result<CreateMessage> {
+    let mut title = format!("Simulated {} {} times", inline_code(expr), sim.runs);
+    if sim.timed_out {
+        title.push_str(&format!(
+            " (of {requested}, ran out of time after {} seconds)",
+            SIM_TIME_LIMIT.as_secs()
+        ));
+    }
+    let mut stats = String::new();
+    if let (Some(mean), Some(stddev)) = (sim.mean(), sim.stddev()) {
+        stats.push_str(&format!("mean    {mean:.3}\nstddev  {stddev:.3}\n"));
+    }
+    if let (Some(min), Some(max)) = (sim.min(), sim.max()) {
+        stats.push_str(&format!("min     {min}\nmax     {max}\n"));
+    }
+    for p in SIM_PERCENTILES {
+        if let Some(v) = sim.percentile(p) {
+            stats.push_str(&format!("{:<8}{v}\n", format!("p{p}")));
+        }
+    }
+    let hist = Histogram::from_simulation(sim);
+    let text = format!("{title}\n```\n{stats}\n{}```", hist.to_text());
+    Ok(
+        if hist.bars.len() <= TEXT_CHART_MAX_BARS && text.chars().count() <= MESSAGE_LIMIT {
+            CreateMessage::new().content(text)
+        } else {
+            CreateMessage::new()
+                .content(format!("{title}\n```\n{stats}```"))
+                .add_file(CreateAttachment::bytes(hist.to_png()?, "sim.png"))
+        },
+    )
+}
+
+/// Wraps `s` in inline code formatting, which also stops it from pinging anyone.
+fn inline_code(s: &str) -> String {
+    // zero-width space, prevent "``"
+    format!("``{}``", s.replace('`', "`\u{200b}"))
+}
+
 async fn reply(ctx: &Context, msg: Message, builder: CreateMessage) -> serenity::Result<()> {
     let msgref = MessageReference::from((msg.channel_id, msg.id));
     msg.channel_id
@@ -30,9 +187,19 @@ impl Handler {
     fn new() -> Self {
         Self {
             start_time: Instant::now(),
+            sessions: Mutex::new(HashMap::new()),
+            decks: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Runs `f` on the channel's deck, which is a shuffled standard deck if it didn't have one.
+    fn with_deck<T>(&self, channel: ChannelId, f: impl FnOnce(&mut Deck) -> T) -> T {
+        let mut decks = self.decks.lock().unwrap();
+        f(decks
+            .entry(channel)
+            .or_insert_with(|| shuffled(Deck::standard())))
+    }
+
     fn uptime(&self) -> String {
         let mut time_elapsed = Instant::now().duration_since(self.start_time).as_secs();
         let secs = time_elapsed % 60;
@@ -49,19 +216,84 @@ impl Handler {
         if let Some(first_word) = words.next() {
             match &first_word[PREFIX.len()..] {
                 "roll" | "eval" | "evaluate" | "calc" | "calculate" => {
-                    if let Some((_, expr)) = content.split_once(' ') {
-                        let mut expr = expr.trim();
-                        if expr.starts_with("```") && expr.ends_with("```") {
-                            expr = &expr[3..expr.len() - 3];
-                        } else if expr.starts_with('`') && expr.ends_with('`') {
-                            expr = &expr[1..expr.len() - 1];
-                        }
-                        match tokio::time::timeout(Duration::from_millis(50), dice::eval(expr))
-                            .await
+                    if let Some((_, args)) = content.split_once(' ') {
+                        let (args, mut seed) = seed_arg(args);
+                        let expr = strip_code(args);
+                        let mut receipt = None;
+                        let mut seed_in_session = false;
+                        if let Some(session) =
+                            self.sessions.lock().unwrap().get_mut(&msg.channel_id)
+                        {
+                            if seed.is_some() {
+                                seed_in_session = true;
+                            } else {
+                                let (index, roll_seed) = session.next_roll();
+                                seed = Some(roll_seed);
+                                receipt = Some(format!(
+                                    "roll #{index} of session {}, ",
+                                    &session.commitment()[..12]
+                                ));
+                            }
+                        }
+                        if seed_in_session {
+                            // a seed of the roller's choosing could pick the result for them
+                            let s = "A `%commit` session is running in this channel, so rolls can't choose their own seed. `%reveal` ends the session.";
+                            reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                            return Ok(());
+                        }
+                        let stored = self.decks.lock().unwrap().get(&msg.channel_id).cloned();
+                        let deck = stored.clone().unwrap_or_else(|| shuffled(Deck::standard()));
+                        let options = dice::EvalOptions {
+                            seed,
+                            deck: Some(deck.clone()),
+                            ..Default::default()
+                        };
+                        match tokio::time::timeout(
+                            Duration::from_millis(50),
+                            dice::eval_with(expr, options),
+                        )
+                        .await
                         {
                             Ok(evalres) => match evalres {
                                 Ok(v) => {
-                                    let s = format!("{v}");
+                                    // only keep the deck if the expression drew from it, and
+                                    // nothing else changed the deck while it was rolling
+                                    if let Some(drawn) = v.deck.as_ref().filter(|d| **d != deck) {
+                                        let changed = {
+                                            let mut decks = self.decks.lock().unwrap();
+                                            let changed =
+                                                decks.get(&msg.channel_id) != stored.as_ref();
+                                            if !changed {
+                                                decks.insert(msg.channel_id, drawn.clone());
+                                            }
+                                            changed
+                                        };
+                                        if changed {
+                                            let s = "The deck changed while rolling, so the cards weren't drawn. Try again.";
+                                            reply(&ctx, msg, CreateMessage::new().content(s))
+                                                .await?;
+                                            return Ok(());
+                                        }
+                                    }
+                                    let marker = crit_marker(&v);
+                                    let mut s = format!("{marker}{}", v.value);
+                                    if let (false, Some(seed)) = (v.rolls.is_empty(), v.seed) {
+                                        let footer = format!(
+                                            "\n-# {}seed={seed}",
+                                            receipt.unwrap_or_default()
+                                        );
+                                        let detailed = format!(
+                                            "{marker}{} → {} = {}{footer}",
+                                            inline_code(expr),
+                                            v.breakdown(),
+                                            v.value
+                                        );
+                                        s = if detailed.chars().count() <= MESSAGE_LIMIT {
+                                            detailed
+                                        } else {
+                                            format!("{s}{footer}")
+                                        };
+                                    }
                                     reply(&ctx, msg, CreateMessage::new().content(s)).await?;
                                 }
                                 Err(e) => {
@@ -80,6 +312,248 @@ impl Handler {
                         }
                     }
                 }
+                "dist" | "distribution" => {
+                    if let Some(expr) = expr_arg(content) {
+                        let hist = match tokio::time::timeout(
+                            Duration::from_millis(500),
+                            dice::distribution(expr),
+                        )
+                        .await
+                        {
+                            Ok(Ok(dist)) => Ok(Histogram::from_distribution(&dist)),
+                            // Expressions that set variables can only be rolled, and some take
+                            // too long to work out exactly, so roll those lots of times instead.
+                            _ => tokio::time::timeout(
+                                Duration::from_millis(550),
+                                dice::simulate(expr, DIST_SAMPLES, Duration::from_millis(500)),
+                            )
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(anyhow::anyhow!(
+                                    "exceeded max duration of 500 milliseconds, execution halted"
+                                ))
+                            })
+                            .map(|sim| Histogram::from_simulation(&sim)),
+                        };
+                        match hist {
+                            Ok(hist) => {
+                                let title = match hist.samples {
+                                    None => format!("Distribution of {}", inline_code(expr)),
+                                    Some(n) => format!(
+                                        "Distribution of {} over {n} rolls",
+                                        inline_code(expr)
+                                    ),
+                                };
+                                let text = format!("{title}\n```\n{}```", hist.to_text());
+                                let builder = if hist.bars.len() <= TEXT_CHART_MAX_BARS
+                                    && text.chars().count() <= MESSAGE_LIMIT
+                                {
+                                    CreateMessage::new().content(text)
+                                } else {
+                                    CreateMessage::new().content(title).add_file(
+                                        CreateAttachment::bytes(hist.to_png()?, "dist.png"),
+                                    )
+                                };
+                                reply(&ctx, msg, builder).await?;
+                            }
+                            Err(e) => {
+                                reply(
+                                    &ctx,
+                                    msg,
+                                    CreateMessage::new().content(format!("Evaluation error: {e}")),
+                                )
+                                .await?;
+                            }
+                        }
+                    }
+                }
+                "sim" | "simulate" => {
+                    let args = content
+                        .split_once(' ')
+                        .map(|(_, args)| args.trim_start())
+                        .and_then(|args| args.split_once(' '))
+                        .map(|(runs, expr)| (runs.parse::<usize>(), strip_code(expr)));
+                    let builder = match args {
+                        Some((Ok(runs), expr)) if (1..=SIM_MAX_RUNS).contains(&runs) => {
+                            // the time limit is only checked between rolls, so this catches a
+                            // single roll that takes forever
+                            match tokio::time::timeout(
+                                SIM_TIME_LIMIT + Duration::from_millis(50),
+                                dice::simulate(expr, runs, SIM_TIME_LIMIT),
+                            )
+                            .await
+                            {
+                                Ok(Ok(sim)) => sim_message(expr, runs, &sim)?,
+                                Ok(Err(e)) => {
+                                    CreateMessage::new().content(format!("Evaluation error: {e}"))
+                                }
+                                Err(_) => CreateMessage::new()
+                                    .content("Simulation exceeded max duration, execution halted"),
+                            }
+                        }
+                        Some((Ok(_), _)) => CreateMessage::new().content(format!(
+                            "The number of rolls must be between 1 and {SIM_MAX_RUNS}"
+                        )),
+                        _ => CreateMessage::new().content(
+                            "Usage: `%sim` *`rolls expression`*, e.g. `%sim 10000 4d6kh3`",
+                        ),
+                    };
+                    reply(&ctx, msg, builder).await?;
+                }
+                "commit" => {
+                    let s = match self.sessions.lock().unwrap().entry(msg.channel_id) {
+                        Entry::Occupied(e) => format!(
+                            "A session is already running in this channel, with commitment `{}`. `%reveal` ends it.",
+                            e.get().commitment()
+                        ),
+                        Entry::Vacant(e) => format!(
+                            concat!(
+                                "Started a verifiable session, with commitment `{}`.\n",
+                                "Every roll in this channel is now seeded from a secret with this SHA-256 hash, ",
+                                "until `%reveal` shows the secret."
+                            ),
+                            e.insert(Session::new()).commitment()
+                        ),
+                    };
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
+                "reveal" => {
+                    let session = self.sessions.lock().unwrap().remove(&msg.channel_id);
+                    let s = match session {
+                        Some(session) => format!(
+                            concat!(
+                                "Ended the session with commitment `{}`, after {} rolls.\n",
+                                "The secret was `{}`.\n",
+                                "Check roll #N of it with `dicetest`: `receipt {} N expression`"
+                            ),
+                            session.commitment(),
+                            session.rolls(),
+                            session.secret(),
+                            session.secret(),
+                        ),
+                        None => "There's no session running in this channel. `%commit` starts one."
+                            .to_string(),
+                    };
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
+                "table" => {
+                    // the tables are read every time, so edits to them show up straight away
+                    let tables = Tables::load(table::tables_dir()).await;
+                    let builder = match (tables, words.next()) {
+                        (Err(e), _) => CreateMessage::new().content(format!("Table error: {e}")),
+                        (Ok(tables), None) => {
+                            let names: Vec<_> = tables.names().map(inline_code).collect();
+                            let mut s = if names.is_empty() {
+                                "There aren't any tables yet.".to_string()
+                            } else {
+                                format!("Tables: {}", names.join(", "))
+                            };
+                            for problem in tables.problems() {
+                                s += &format!("\nCouldn't load a table: {problem}");
+                            }
+                            CreateMessage::new().content(s)
+                        }
+                        (Ok(tables), Some(name)) => {
+                            match tokio::time::timeout(
+                                Duration::from_millis(500),
+                                tables.roll(name, None),
+                            )
+                            .await
+                            {
+                                Ok(Ok(res)) => {
+                                    let text = format!("**{}** ({}): {}", res.table, res.roll, res.text);
+                                    if text.chars().count() <= MESSAGE_LIMIT {
+                                        CreateMessage::new().content(text)
+                                    } else {
+                                        CreateMessage::new()
+                                            .content(format!("**{}** ({})", res.table, res.roll))
+                                            .add_file(CreateAttachment::bytes(res.text.into_bytes(), "table.txt"))
+                                    }
+                                }
+                                Ok(Err(e)) => {
+                                    CreateMessage::new().content(format!("Table error: {e}"))
+                                }
+                                Err(_) => CreateMessage::new().content(
+                                    "Rolling on the table exceeded max duration of 500 milliseconds, execution halted",
+                                ),
+                            }
+                        }
+                    };
+                    reply(&ctx, msg, builder).await?;
+                }
+                "deck" => {
+                    let arg = content.split_once(' ').map_or("", |(_, arg)| arg.trim());
+                    let s = if arg.is_empty() {
+                        self.with_deck(msg.channel_id, |deck| deck_status(deck))
+                    } else {
+                        let deck = match arg.strip_prefix("custom ") {
+                            Some(list) => Deck::custom(list).map(Some),
+                            None => Ok(Deck::from_name(arg)),
+                        };
+                        match deck {
+                            Ok(Some(deck)) => {
+                                let deck = shuffled(deck);
+                                let s =
+                                    format!("Shuffled a new deck of {} cards.", deck.remaining());
+                                self.decks.lock().unwrap().insert(msg.channel_id, deck);
+                                s
+                            }
+                            Ok(None) => {
+                                "Usage: `%deck` *`[standard|jokers|tarot|custom card, card, ...]`*"
+                                    .to_string()
+                            }
+                            Err(e) => format!("Deck error: {e}"),
+                        }
+                    };
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
+                "draw" => {
+                    let builder = match words.next().map(str::parse::<usize>).unwrap_or(Ok(1)) {
+                        Ok(n) => match self.with_deck(msg.channel_id, |deck| {
+                            deck.draw(n).map(|cards| (cards, deck.remaining()))
+                        }) {
+                            Ok((cards, remaining)) => drawn_message(&cards, remaining),
+                            Err(e) => CreateMessage::new().content(format!("Deck error: {e}")),
+                        },
+                        Err(_) => CreateMessage::new()
+                            .content("Usage: `%draw` *`[cards]`*, e.g. `%draw 3`"),
+                    };
+                    reply(&ctx, msg, builder).await?;
+                }
+                "discard" => {
+                    let arg = content.split_once(' ').map_or("", |(_, arg)| arg.trim());
+                    let s = self.with_deck(msg.channel_id, |deck| {
+                        if arg.is_empty() {
+                            let n = deck.drawn().len();
+                            deck.discard_all();
+                            return format!("Discarded {n} cards. {}", deck_status(deck));
+                        }
+                        // all of the cards or none of them
+                        let mut discarded = deck.clone();
+                        for card in arg.split(',') {
+                            if let Err(e) = discarded.discard(card.trim()) {
+                                return format!("Deck error: {e}");
+                            }
+                        }
+                        *deck = discarded;
+                        format!("Discarded. {}", deck_status(deck))
+                    });
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
+                "shuffle" => {
+                    let s = self.with_deck(msg.channel_id, |deck| {
+                        deck.shuffle(&mut rand::thread_rng());
+                        format!("Shuffled the discard pile back in. {}", deck_status(deck))
+                    });
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
+                "reset" => {
+                    let s = self.with_deck(msg.channel_id, |deck| {
+                        deck.reset(&mut rand::thread_rng());
+                        format!("Put every card back and shuffled. {}", deck_status(deck))
+                    });
+                    reply(&ctx, msg, CreateMessage::new().content(s)).await?;
+                }
                 "ping" => {
                     reply(&ctx, msg, CreateMessage::new().content("pong.")).await?;
                 }
@@ -112,9 +586,26 @@ impl Handler {
                                     **Common examples**
                                     `%roll 4d6+7`: Roll 4 6-sided dice, add them all up, and add 7 to that.
                                     `%roll 2d20H1`: Roll 2 20-sided dice and choose the **h**ighest one. (Advantage. For disadvantage, use `L`.)
+                                    `%roll 4d6dl1`: Roll 4 6-sided dice and **d**rop the **l**owest one. (Use `dh` to drop the highest.)
                                     `%roll d2!`: Roll an exploding d2.
                                     `%roll d10!(9,10)!`: Roll a d10 that explodes on outcomes of either 9 or 10.
+                                    `%roll 4d6!>4`: Roll 4 6-sided dice that explode on anything above 4. (`!!` compounds the extra rolls into one die, `!p` penetrates. With a space, like `d20! > 15`, the roll is compared instead.)
+                                    `%roll 2d6r<3`: Roll 2 6-sided dice, rerolling any 1s and 2s. (Use `ro` to only reroll once, and `r(1,2)` to list the faces.)
+                                    `%roll 8d10s7f1`: Roll 8 10-sided dice and count how many show 7 or higher, minus how many show 1. (Add `dbl10` to count 10s twice.)
+                                    `%roll P(2d6 >= 8)`: The chance of rolling 8 or more on 2d6, without rolling anything. `E(4d6kh3)` gives the average instead, and `stddev` and `median` work too.
+                                    `%roll faces(5d10u sd)`: Roll 5 10-sided dice that all land on different faces, and list them from highest to lowest. (`s` or `sa` sorts lowest first, and also works on arrays.)
+                                    `%roll pool = faces(5d10); pool >= 7`: Keep the 5 dice as a list rather than adding them up, and check each of them against 7. (`#pool` is how many dice there are.)
+                                    `%roll wild(d8)`: Savage Worlds: roll an exploding d8 trait die and an exploding d6 wild die, and take the higher. Both are shown. (`max(d8!, d6!)` does the same, and `min` takes the lower.)
                                     `%roll d[1,4,5]`: Roll a dice with 3 custom sides: one side with 1, one side with 4, and one side with 5.
+                                    `%roll 4dF`: Roll 4 Fate dice, with sides -1, -1, 0, 0, +1, +1. (`dF.1` has four blank sides instead, and `d%` is a d100.)
+                                    `%roll d{"common": 7, "uncommon": 2, "rare": 1}`: Roll a die whose sides come up 7, 2 and 1 times out of 10.
+                                    `%roll 2dAbility + dProficiency + 2dDifficulty`: Genesys and Star Wars: roll narrative dice, with successes cancelling failures and advantages cancelling threats. (Also `dBoost`, `dSetback`, `dChallenge` and `dForce`.)
+                                    `%roll cancel({"hope": 2}, "hope", "fear") + {"fear": 1}`: Bags of symbols add up by pooling them; `cancel` makes two symbols cancel each other out.
+                                    `%roll d20+5`: Roll a d20 and add 5. A natural 20 or 1 is bolded and called out, as is the highest or lowest face on any other die.
+                                    `%roll check(d100, 45)`: Roll a d100 against a skill of 45, and say whether that's a critical, extreme, hard or regular success, a failure, or a fumble.
+                                    `%roll 6x(4d6kh3)`: Roll 4d6 and keep the highest 3, six separate times, for a list of six ability scores. (`repeat(6, 4d6kh3)` does the same.)
+                                    `%roll [draw(2), d6]`: Draw 2 cards from the channel's deck (see `%help deck`), as a list of their names.
+                                    `%roll 4d6 seed=12345`: Roll 4d6 with a fixed seed, reproducing an earlier roll exactly. Every reply with dice in it shows the seed it was rolled with.
                                     `%roll d["yes","no","maybe"]`: Choose between the outcomes "yes", "no", and "maybe" at random.
                                 "#})
                                 .field("Regular operators", op_list, false)
@@ -123,6 +614,72 @@ impl Handler {
                             // TODO pages
                             reply(&ctx, msg, builder).await?;
                         }
+                        Some("dist") => {
+                            let embed = CreateEmbed::new()
+                                .title("`%dist`*`expression`*")
+                                .color(0xA526B3)
+                                .description(indoc::indoc! {r#"
+                                    Synonyms: **`%distribution`**
+                                    Charts how likely each outcome of the expression is, e.g. `%dist 3d6`.
+                                    The chances are exact when possible. Otherwise, the expression is rolled lots of times instead.
+                                    Expressions with lots of different outcomes get a picture instead of a text chart.
+                                "#});
+                            let builder = CreateMessage::new().embed(embed);
+                            reply(&ctx, msg, builder).await?;
+                        }
+                        Some("sim") => {
+                            let embed = CreateEmbed::new()
+                                .title("`%sim`*`rolls expression`*")
+                                .color(0xA526B3)
+                                .description(indoc::indoc! {r#"
+                                    Synonyms: **`%simulate`**
+                                    Rolls the expression lots of times, e.g. `%sim 10000 4d6kh3`, and reports the mean, standard deviation, lowest and highest results, percentiles, and how often each result came up.
+                                    Works on anything `%roll` does, even when `%dist` can't work out the exact chances.
+                                    At most 100000 rolls are done, and the simulation stops early if it takes more than 2 seconds.
+                                "#});
+                            let builder = CreateMessage::new().embed(embed);
+                            reply(&ctx, msg, builder).await?;
+                        }
+                        Some("commit") | Some("reveal") => {
+                            let embed = CreateEmbed::new()
+                                .title("`%commit`, `%reveal`")
+                                .color(0xA526B3)
+                                .description(indoc::indoc! {r#"
+                                    Proves the bot isn't fudging rolls.
+                                    `%commit` starts a session in the channel, and shows the SHA-256 hash of a secret it picks. Every `%roll` in the channel after that is seeded from the secret and the roll's number, which is shown under the roll. Rolls can't pick their own `seed=` until the session ends.
+                                    `%reveal` ends the session and shows the secret, so anyone can check it against the hash, and redo any roll from the session with `dicetest`.
+                                "#});
+                            let builder = CreateMessage::new().embed(embed);
+                            reply(&ctx, msg, builder).await?;
+                        }
+                        Some("table") => {
+                            let embed = CreateEmbed::new()
+                                .title("`%table`*`[name]`*")
+                                .color(0xA526B3)
+                                .description(indoc::indoc! {r#"
+                                    Rolls on one of the bot's random tables, e.g. `%table encounters`. Without a name, lists the tables there are.
+                                    Tables are text files in the bot's tables directory, with one entry per line, like `01-15 Goblins`. Entries can roll dice, like `[[2d4]] wolves`, and roll on other tables, like `a chest of {{loot}}`.
+                                    A `dice: 2d6` line says what to roll, if it isn't a die with as many sides as the table has rolls. Lines without ranges are all equally likely.
+                                "#});
+                            let builder = CreateMessage::new().embed(embed);
+                            reply(&ctx, msg, builder).await?;
+                        }
+                        Some("deck" | "draw" | "discard" | "shuffle" | "reset") => {
+                            let embed = CreateEmbed::new()
+                                .title("`%deck`, `%draw`, `%discard`, `%shuffle`, `%reset`")
+                                .color(0xA526B3)
+                                .description(indoc::indoc! {r#"
+                                    Each channel has a deck of cards, which remembers what's been drawn from it. It starts out as a shuffled standard 52-card deck.
+                                    `%deck`: Show how many cards are left, drawn, and discarded.
+                                    `%deck tarot`: Start a new shuffled deck. There's `standard`, `jokers` (54 cards, for Deadlands), `tarot`, and `custom Sword, Shield, Potion` for any cards you like.
+                                    `%draw 3`: Draw 3 cards. `draw(3)` in `%roll` does the same, e.g. `%roll [draw(1), d6]`.
+                                    `%discard A♠, 10♥`: Put drawn cards on the discard pile. Without any cards, every drawn card is discarded.
+                                    `%shuffle`: Shuffle the discard pile back into the deck.
+                                    `%reset`: Put every card back, drawn or not, and shuffle.
+                                "#});
+                            let builder = CreateMessage::new().embed(embed);
+                            reply(&ctx, msg, builder).await?;
+                        }
                         Some("ping") => {
                             let embed = CreateEmbed::new()
                                 .title("`%ping`")
@@ -183,8 +740,13 @@ impl Handler {
                             let embed = CreateEmbed::new()
                                 .title("All commands")
                                 .color(0xA526B3)
-                                .description("TL;DR: `%roll`, `%help`, `%checkhealth`, `%ping`")
+                                .description("TL;DR: `%roll`, `%dist`, `%sim`, `%commit`, `%reveal`, `%deck`, `%draw`, `%table`, `%help`, `%checkhealth`, `%ping`")
                                 .field("`%roll`", "Calculate dice values, with arbitrary mathematical expressions.\ne.g. `%roll 4d6+7`\n Synonyms: **`%calc`, `%eval`**", false)
+                                .field("`%dist`", "Chart how likely each outcome of an expression is.\ne.g. `%dist 3d6`", false)
+                                .field("`%sim`", "Roll an expression lots of times and report statistics.\ne.g. `%sim 10000 4d6kh3`", false)
+                                .field("`%commit`, `%reveal`", "Start a session of rolls that can be checked afterwards, and end it by revealing its secret.", false)
+                                .field("`%deck`, `%draw`", "Draw cards from the channel's deck, without putting them back until it's shuffled.\ne.g. `%draw 3`", false)
+                                .field("`%table`", "Roll on one of the GM's random tables.\ne.g. `%table encounters`", false)
                                 .field("`%help`", "Display the help-page for a specific command.\ne.g. `%help roll`.", false)
                                 .field("`%checkhealth`", "Display a dialog with bot health information.", true)
                                 .field("`%ping`", "Make the bot respond `pong.`", true);
@@ -225,3 +787,27 @@ async fn main() {
 
     client.start().await.unwrap();
 }
+
+#[tokio::test]
+async fn crit_marker_test() {
+    async fn marker(expr: &str, seed: u64) -> String {
+        let options = dice::EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        crit_marker(&dice::eval_with(expr, options).await.unwrap())
+    }
+
+    // highs and lows on damage dice, Fate dice and checks aren't nat 20s
+    for seed in 0..100 {
+        for expr in ["8d6", "4dF", "check(d100, 45)", "check(d20, 10)"] {
+            assert_eq!(marker(expr, seed).await, "", "{expr} seed={seed}");
+        }
+    }
+    let mut markers: Vec<_> = Vec::new();
+    for seed in 0..100 {
+        markers.push(marker("d20", seed).await);
+    }
+    assert!(markers.contains(&"**NAT 20!** ".to_string()));
+    assert!(markers.contains(&"**NAT 1!** ".to_string()));
+}
@@ -0,0 +1,174 @@
+//! PNG rendering for [`Histogram`]s, with a tiny built-in font so nothing has to be fetched.
+
+use super::Histogram;
+
+const MARGIN: usize = 16;
+const PLOT_HEIGHT: usize = 240;
+/// The plot is at most this wide. With more outcomes than that, neighbouring ones share a bar.
+const MAX_PLOT_WIDTH: usize = 720;
+const MIN_WIDTH: usize = 240;
+const MAX_BAR_WIDTH: usize = 24;
+
+/// How much each pixel of the font is scaled up by.
+const FONT_SCALE: usize = 2;
+const GLYPH_WIDTH: usize = 3 * FONT_SCALE;
+const GLYPH_HEIGHT: usize = 5 * FONT_SCALE;
+const GLYPH_ADVANCE: usize = GLYPH_WIDTH + FONT_SCALE;
+
+const BACKGROUND: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const BAR: [u8; 3] = [0xA5, 0x26, 0xB3];
+const AXIS: [u8; 3] = [0x80, 0x80, 0x80];
+const TEXT: [u8; 3] = [0x20, 0x20, 0x20];
+
+/// A 3x5 bitmap of `c`, one row per byte, with the leftmost pixel in the highest of the low 3 bits.
+/// Characters without a glyph are left blank.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => [0; 5],
+    }
+}
+
+fn text_width(s: &str) -> usize {
+    (s.chars().count() * GLYPH_ADVANCE).saturating_sub(FONT_SCALE)
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                let i = (row * self.width + col) * 3;
+                self.pixels[i..i + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            let gx = x + i * GLYPH_ADVANCE;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        self.fill(
+                            gx + col * FONT_SCALE,
+                            y + row * FONT_SCALE,
+                            FONT_SCALE,
+                            FONT_SCALE,
+                            TEXT,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draws a bar per outcome, with the highest probability in the top left, and the lowest and
+/// highest outcomes under the first and last bars. If there are too many outcomes to fit, each bar
+/// is as tall as the likeliest of the outcomes it covers.
+pub fn render(hist: &Histogram) -> anyhow::Result<Vec<u8>> {
+    let per_bar = hist.bars.len().div_ceil(MAX_PLOT_WIDTH).max(1);
+    let bars: Vec<f64> = hist
+        .bars
+        .chunks(per_bar)
+        .map(|outcomes| outcomes.iter().map(|(_, p)| *p).fold(0., f64::max))
+        .collect();
+    let n = bars.len().max(1);
+    let bar_width = (MAX_PLOT_WIDTH / n).clamp(1, MAX_BAR_WIDTH);
+    let gap = if bar_width >= 4 { 1 } else { 0 };
+    let plot_width = n * bar_width;
+    let width = (plot_width + 2 * MARGIN).max(MIN_WIDTH);
+    let plot_top = MARGIN + GLYPH_HEIGHT + 4;
+    let axis = plot_top + PLOT_HEIGHT;
+    let height = axis + 1 + 4 + GLYPH_HEIGHT + MARGIN;
+    let mut canvas = Canvas::new(width, height);
+
+    let max = hist.max_probability();
+    canvas.text(MARGIN, MARGIN, &format!("{:.2}%", max * 100.));
+    for (i, p) in bars.iter().enumerate() {
+        let mut h = (p / max * PLOT_HEIGHT as f64).round() as usize;
+        if h == 0 && *p > 0. {
+            h = 1;
+        }
+        let x = MARGIN + i * bar_width;
+        canvas.fill(x, axis - h, bar_width - gap, h, BAR);
+    }
+    canvas.fill(MARGIN, axis, plot_width, 1, AXIS);
+    let label_y = axis + 1 + 4;
+    if let Some((first, _)) = hist.bars.first() {
+        canvas.text(MARGIN, label_y, first);
+    }
+    if let Some((last, _)) = hist.bars.last().filter(|_| hist.bars.len() > 1) {
+        let x = (MARGIN + plot_width).saturating_sub(text_width(last));
+        canvas.text(x, label_y, last);
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&canvas.pixels)?;
+    Ok(out)
+}
+
+#[tokio::test]
+async fn render_test() {
+    let dist = crate::dice::distribution("3d6").await.unwrap();
+    let png = Histogram::from_distribution(&dist).to_png().unwrap();
+    let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    let (w, h) = (info.width as usize, info.height as usize);
+    assert_eq!(w, 16 * MAX_BAR_WIDTH + 2 * MARGIN);
+    let px = |x: usize, y: usize| &pixels[(y * w + x) * 3..(y * w + x) * 3 + 3];
+    // the 10 and 11 bars reach the top of the plot, but 3 and 18 barely show up
+    let plot_top = MARGIN + GLYPH_HEIGHT + 4;
+    let bar_x = |i: usize| MARGIN + i * MAX_BAR_WIDTH + 2;
+    assert_eq!(px(bar_x(7), plot_top), BAR);
+    assert_eq!(px(bar_x(8), plot_top), BAR);
+    assert_eq!(px(bar_x(0), plot_top), BACKGROUND);
+    assert_eq!(px(bar_x(0), plot_top + PLOT_HEIGHT - 1), BAR);
+    assert_eq!(px(0, h - 1), BACKGROUND);
+
+    // lots of outcomes get squashed down to thin bars, and then share them
+    let dist = crate::dice::distribution("d500").await.unwrap();
+    let png = Histogram::from_distribution(&dist).to_png().unwrap();
+    let reader = png::Decoder::new(&png[..]).read_info().unwrap();
+    assert_eq!(reader.info().width as usize, 500 + 2 * MARGIN);
+    for expr in ["d1000", "d5000"] {
+        let dist = crate::dice::distribution(expr).await.unwrap();
+        let png = Histogram::from_distribution(&dist).to_png().unwrap();
+        let reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        assert!(
+            reader.info().width as usize <= MAX_PLOT_WIDTH + 2 * MARGIN,
+            "{expr}"
+        );
+    }
+}
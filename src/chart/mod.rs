@@ -0,0 +1,119 @@
+//! Charts of how likely each outcome of an expression is, for `%dist`.
+
+mod image;
+
+use crate::dice::{Distribution, Simulation};
+
+/// How many characters wide the longest bar of a text chart is.
+const TEXT_BAR_WIDTH: usize = 30;
+
+/// How likely each outcome of an expression is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Each outcome, in ascending order, along with its probability.
+    pub bars: Vec<(String, f64)>,
+    /// How many times the expression was rolled to get this, or `None` if it's exact.
+    pub samples: Option<usize>,
+}
+
+impl Histogram {
+    pub fn from_distribution(dist: &Distribution) -> Self {
+        Self {
+            bars: dist
+                .outcomes()
+                .iter()
+                .map(|(v, p)| (v.to_string(), p.to_f64()))
+                .collect(),
+            samples: None,
+        }
+    }
+
+    pub fn from_simulation(sim: &Simulation) -> Self {
+        Self {
+            bars: sim
+                .frequencies
+                .iter()
+                .map(|(v, n)| (v.to_string(), *n as f64 / sim.runs as f64))
+                .collect(),
+            samples: Some(sim.runs),
+        }
+    }
+
+    /// The highest probability of any outcome.
+    fn max_probability(&self) -> f64 {
+        self.bars.iter().map(|(_, p)| *p).fold(0., f64::max)
+    }
+
+    /// Renders a bar per outcome, like `3   0.46% █▏`, meant to go in a code block.
+    pub fn to_text(&self) -> String {
+        const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+        let label_width = self
+            .bars
+            .iter()
+            .map(|(l, _)| l.chars().count())
+            .max()
+            .unwrap_or(0);
+        let max = self.max_probability();
+        let mut s = String::new();
+        for (label, p) in &self.bars {
+            let mut eighths = (p / max * (TEXT_BAR_WIDTH * 8) as f64).round() as usize;
+            if eighths == 0 && *p > 0. {
+                eighths = 1;
+            }
+            let mut bar = "█".repeat(eighths / 8);
+            let rest = eighths % 8;
+            if rest != 0 {
+                bar.push(EIGHTHS[rest]);
+            }
+            let line = format!("{label:>label_width$} {:>6.2}% {bar}", p * 100.);
+            s.push_str(line.trim_end());
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Renders the histogram as a PNG bar chart.
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        image::render(self)
+    }
+}
+
+#[tokio::test]
+async fn histogram_test() {
+    use crate::dice;
+    use std::time::Duration;
+
+    let hist = Histogram::from_distribution(&dice::distribution("d4").await.unwrap());
+    assert_eq!(hist.samples, None);
+    assert_eq!(
+        hist.to_text(),
+        concat!(
+            "1  25.00% ██████████████████████████████\n",
+            "2  25.00% ██████████████████████████████\n",
+            "3  25.00% ██████████████████████████████\n",
+            "4  25.00% ██████████████████████████████\n",
+        )
+    );
+
+    let hist = Histogram::from_distribution(&dice::distribution("2d2*5").await.unwrap());
+    assert_eq!(
+        hist.to_text(),
+        concat!(
+            "10  25.00% ███████████████\n",
+            "15  50.00% ██████████████████████████████\n",
+            "20  25.00% ███████████████\n",
+        )
+    );
+
+    let hist = Histogram::from_distribution(&dice::distribution("3d6").await.unwrap());
+    let text = hist.to_text();
+    assert_eq!(text.lines().count(), 16);
+    assert!(text.starts_with(" 3   0.46% █▏\n"));
+
+    let sim = dice::simulate("d2", 100, Duration::from_secs(10))
+        .await
+        .unwrap();
+    let hist = Histogram::from_simulation(&sim);
+    assert_eq!(hist.samples, Some(100));
+    assert!((hist.bars.iter().map(|(_, p)| p).sum::<f64>() - 1.).abs() < 1e-9);
+}
@@ -0,0 +1,220 @@
+//! Decks of cards, which remember what's been drawn from them, unlike dice.
+
+use rand::{seq::SliceRandom, Rng};
+use smol_str::SmolStr;
+
+/// The most cards a custom deck can have.
+pub const DECK_LIMIT: usize = 1000;
+
+const SUITS: [&str; 4] = ["♠", "♥", "♦", "♣"];
+const RANKS: [&str; 13] = [
+    "A", "2", "3", "4", "5", "6", "7", "8", "9", "10", "J", "Q", "K",
+];
+
+const MAJOR_ARCANA: [&str; 22] = [
+    "The Fool",
+    "The Magician",
+    "The High Priestess",
+    "The Empress",
+    "The Emperor",
+    "The Hierophant",
+    "The Lovers",
+    "The Chariot",
+    "Strength",
+    "The Hermit",
+    "Wheel of Fortune",
+    "Justice",
+    "The Hanged Man",
+    "Death",
+    "Temperance",
+    "The Devil",
+    "The Tower",
+    "The Star",
+    "The Moon",
+    "The Sun",
+    "Judgement",
+    "The World",
+];
+const TAROT_SUITS: [&str; 4] = ["Wands", "Cups", "Swords", "Pentacles"];
+const TAROT_RANKS: [&str; 14] = [
+    "Ace", "Two", "Three", "Four", "Five", "Six", "Seven", "Eight", "Nine", "Ten", "Page",
+    "Knight", "Queen", "King",
+];
+
+/// A deck of cards that are drawn without replacement, until they're shuffled back in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deck {
+    /// The cards left to draw, with the top of the deck last.
+    pile: Vec<SmolStr>,
+    /// Cards that have been drawn, and not discarded yet.
+    drawn: Vec<SmolStr>,
+    discards: Vec<SmolStr>,
+}
+
+impl Deck {
+    /// A deck of `cards`, in order, with the first on top. Nothing shuffles it until
+    /// [`Deck::reset`] or [`Deck::shuffle`].
+    pub fn new(cards: impl IntoIterator<Item = SmolStr>) -> Self {
+        let mut pile: Vec<_> = cards.into_iter().collect();
+        pile.reverse();
+        Self {
+            pile,
+            drawn: Vec::new(),
+            discards: Vec::new(),
+        }
+    }
+
+    /// The standard 52-card deck, like `A♠` and `10♥`.
+    pub fn standard() -> Self {
+        Self::new(SUITS.iter().flat_map(|suit| {
+            RANKS
+                .iter()
+                .map(move |rank| SmolStr::new(format!("{rank}{suit}")))
+        }))
+    }
+
+    /// The standard deck with a red and a black joker, as used by Deadlands.
+    pub fn with_jokers() -> Self {
+        let mut deck = Self::standard();
+        for joker in ["Black Joker", "Red Joker"] {
+            deck.pile.insert(0, joker.into());
+        }
+        deck
+    }
+
+    /// The 78-card tarot deck, with the major arcana first.
+    pub fn tarot() -> Self {
+        let minor = TAROT_SUITS.iter().flat_map(|suit| {
+            TAROT_RANKS
+                .iter()
+                .map(move |rank| SmolStr::new(format!("{rank} of {suit}")))
+        });
+        Self::new(MAJOR_ARCANA.iter().map(|&card| card.into()).chain(minor))
+    }
+
+    /// The deck `name`, which is `standard`, `jokers` or `tarot`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(Self::standard()),
+            "jokers" => Some(Self::with_jokers()),
+            "tarot" => Some(Self::tarot()),
+            _ => None,
+        }
+    }
+
+    /// A deck of the cards listed in `list`, separated by commas.
+    pub fn custom(list: &str) -> anyhow::Result<Self> {
+        let cards: Vec<_> = list.split(',').map(str::trim).collect();
+        if cards.iter().any(|card| card.is_empty()) {
+            anyhow::bail!("cards can't be blank".to_string());
+        }
+        if cards.len() > DECK_LIMIT {
+            anyhow::bail!("too many cards: {} > {DECK_LIMIT}", cards.len());
+        }
+        Ok(Self::new(cards.into_iter().map(SmolStr::new)))
+    }
+
+    /// How many cards are left to draw.
+    pub fn remaining(&self) -> usize {
+        self.pile.len()
+    }
+
+    /// The cards that have been drawn and not discarded, in the order they were drawn.
+    pub fn drawn(&self) -> &[SmolStr] {
+        &self.drawn
+    }
+
+    /// The discard pile, in the order the cards were discarded.
+    pub fn discards(&self) -> &[SmolStr] {
+        &self.discards
+    }
+
+    /// Draws `n` cards off the top of the deck.
+    pub fn draw(&mut self, n: usize) -> anyhow::Result<Vec<SmolStr>> {
+        if n > self.pile.len() {
+            anyhow::bail!(
+                "can't draw {n} cards, there are only {} left in the deck",
+                self.pile.len()
+            );
+        }
+        let cards: Vec<_> = self.pile.drain(self.pile.len() - n..).rev().collect();
+        self.drawn.extend(cards.iter().cloned());
+        Ok(cards)
+    }
+
+    /// Moves a drawn card onto the discard pile.
+    pub fn discard(&mut self, card: &str) -> anyhow::Result<()> {
+        let Some(i) = self.drawn.iter().position(|c| c == card) else {
+            anyhow::bail!("`{card}` hasn't been drawn");
+        };
+        let card = self.drawn.remove(i);
+        self.discards.push(card);
+        Ok(())
+    }
+
+    /// Moves every drawn card onto the discard pile.
+    pub fn discard_all(&mut self) {
+        self.discards.append(&mut self.drawn);
+    }
+
+    /// Shuffles the discard pile back into the cards left to draw. Cards that are still drawn
+    /// stay out.
+    pub fn shuffle<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.pile.append(&mut self.discards);
+        self.pile.shuffle(rng);
+    }
+
+    /// Puts every card back, drawn or discarded, and shuffles the whole deck.
+    pub fn reset<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.discard_all();
+        self.shuffle(rng);
+    }
+}
+
+#[test]
+fn deck_test() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut deck = Deck::standard();
+    assert_eq!(deck.remaining(), 52);
+    assert_eq!(deck.draw(2).unwrap(), ["A♠", "2♠"]);
+    assert_eq!(deck.remaining(), 50);
+    assert!(deck.draw(51).is_err());
+    assert_eq!(deck.remaining(), 50);
+    assert_eq!(deck.draw(1).unwrap(), ["3♠"]);
+
+    deck.discard("2♠").unwrap();
+    assert!(deck.discard("2♠").is_err());
+    assert!(deck.discard("K♣").is_err());
+    assert_eq!(deck.drawn(), ["A♠", "3♠"]);
+    assert_eq!(deck.discards(), ["2♠"]);
+
+    // cards in hand stay out of the deck when it's shuffled
+    let mut rng = StdRng::seed_from_u64(0);
+    deck.shuffle(&mut rng);
+    assert_eq!(deck.remaining(), 50);
+    assert!(deck.discards().is_empty());
+    let mut rest = deck.draw(50).unwrap();
+    assert!(!rest.contains(&"A♠".into()));
+    rest.sort();
+    rest.dedup();
+    assert_eq!(rest.len(), 50);
+
+    deck.reset(&mut rng);
+    assert_eq!(deck.remaining(), 52);
+    assert!(deck.drawn().is_empty());
+    assert_ne!(deck, Deck::standard());
+
+    assert_eq!(Deck::with_jokers().remaining(), 54);
+    assert_eq!(Deck::with_jokers().draw(1).unwrap(), ["A♠"]);
+    let mut tarot = Deck::from_name("tarot").unwrap();
+    assert_eq!(tarot.remaining(), 78);
+    assert_eq!(tarot.draw(1).unwrap(), ["The Fool"]);
+    assert_eq!(tarot.draw(22).unwrap()[21], "Ace of Wands");
+    assert_eq!(Deck::from_name("uno"), None);
+
+    let mut custom = Deck::custom("Sword, Shield,Potion").unwrap();
+    assert_eq!(custom.draw(3).unwrap(), ["Sword", "Shield", "Potion"]);
+    assert!(Deck::custom("a,,b").is_err());
+    assert!(Deck::custom(&("a,".repeat(DECK_LIMIT) + "a")).is_err());
+}
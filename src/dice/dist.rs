@@ -0,0 +1,1003 @@
+//! Exact probability distributions of dice expressions, worked out without rolling anything.
+
+use std::collections::BTreeMap;
+
+use rug::{Integer, Rational};
+use smallvec::SmallVec;
+use smol_str::SmolStr;
+
+use crate::dice::{
+    eval::{
+        cancel_symbols, check_degree, dice_count, make_bag, numbered_sides, pick_best,
+        repeat_count, side_weights, sort_array,
+    },
+    lex::{Op, Token},
+    parse::{run_parser, Condition, ParseIns, Tally},
+    value::{
+        array_drop, array_keep, ExplodeMode, FaceMatch, LazyDice, Place, RRVal, ResolveError,
+        SortOrder, POOL_LIMIT, REROLL_LIMIT,
+    },
+};
+
+/// How many times in a row a single die may explode when working out a distribution.
+/// Whatever probability is left over past this is counted as the die stopping there.
+pub const DIST_EXPLOSION_LIMIT: u32 = 20;
+
+/// The most ways a pool can be rolled for the distribution of `faces(...)` to be worked out
+/// exactly, since that goes through every one of them in order.
+const FACES_LIMIT: u32 = 10000;
+
+/// A statistic that can be asked for inside an expression, like `E(4d6kh3)`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stat {
+    /// `P(...)`: the probability that the expression is truthy, e.g. `P(2d6 >= 8)`.
+    Probability,
+    /// `E(...)`: the expected value.
+    Mean,
+    /// `stddev(...)`
+    StdDev,
+    /// `median(...)`
+    Median,
+}
+
+impl Stat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "P" => Stat::Probability,
+            "E" => Stat::Mean,
+            "stddev" => Stat::StdDev,
+            "median" => Stat::Median,
+            _ => return None,
+        })
+    }
+}
+
+/// The exact probability of each outcome of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    outcomes: BTreeMap<RRVal, Rational>,
+}
+
+impl Distribution {
+    fn empty() -> Self {
+        Self {
+            outcomes: BTreeMap::new(),
+        }
+    }
+
+    /// An outcome that's certain.
+    pub fn point(v: RRVal) -> Self {
+        Self {
+            outcomes: BTreeMap::from([(v, Rational::from(1))]),
+        }
+    }
+
+    /// Every possible outcome, in ascending order, along with its probability.
+    pub fn outcomes(&self) -> &BTreeMap<RRVal, Rational> {
+        &self.outcomes
+    }
+
+    /// The probability of getting exactly `v`.
+    pub fn probability(&self, v: &RRVal) -> Rational {
+        self.outcomes.get(v).cloned().unwrap_or_default()
+    }
+
+    /// The probability that the outcome is truthy, e.g. that a comparison holds.
+    pub fn probability_true(&self) -> Rational {
+        self.outcomes
+            .iter()
+            .filter(|(v, _)| v.truthy())
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// Each outcome as an exact number, along with its probability.
+    fn numeric(&self) -> anyhow::Result<Vec<(Rational, &Rational)>> {
+        self.outcomes
+            .iter()
+            .map(|(v, p)| match v {
+                RRVal::Int(n) => Ok((Rational::from(n), p)),
+                RRVal::Float(f) => Rational::from_f64(*f)
+                    .map(|f| (f, p))
+                    .ok_or_else(|| anyhow::anyhow!("{f} isn't a number")),
+                _ => anyhow::bail!("can't do arithmetic on the outcome {v}"),
+            })
+            .collect()
+    }
+
+    /// The expected value.
+    pub fn mean(&self) -> anyhow::Result<Rational> {
+        Ok(self.numeric()?.into_iter().map(|(v, p)| v * p).sum())
+    }
+
+    pub fn variance(&self) -> anyhow::Result<Rational> {
+        let mean = self.mean()?;
+        Ok(self
+            .numeric()?
+            .into_iter()
+            .map(|(v, p)| (v - &mean).square() * p)
+            .sum())
+    }
+
+    /// The lowest outcome that's at least as likely to be beaten as not.
+    pub fn median(&self) -> &RRVal {
+        let half = Rational::from((1, 2));
+        let mut below = Rational::new();
+        for (v, p) in &self.outcomes {
+            below += p;
+            if below >= half {
+                return v;
+            }
+        }
+        unreachable!("probabilities add up to 1")
+    }
+
+    pub fn stat(&self, stat: Stat) -> anyhow::Result<f64> {
+        Ok(match stat {
+            Stat::Probability => self.probability_true().to_f64(),
+            Stat::Mean => self.mean()?.to_f64(),
+            Stat::StdDev => self.variance()?.to_f64().sqrt(),
+            Stat::Median => match self.median() {
+                RRVal::Int(n) => n.to_f64(),
+                RRVal::Float(f) => *f,
+                v => anyhow::bail!("can't take the median of the outcome {v}"),
+            },
+        })
+    }
+
+    /// The outcome, if there's no randomness involved at all.
+    fn as_point(&self) -> Option<&RRVal> {
+        match self.outcomes.len() {
+            1 => self.outcomes.keys().next(),
+            _ => None,
+        }
+    }
+
+    fn add(&mut self, v: RRVal, p: Rational) {
+        *self.outcomes.entry(v).or_default() += p;
+    }
+}
+
+/// Applies `$f` to every outcome of `$d`.
+macro_rules! map_dist {
+    ($d:expr, |$v:ident| $f:expr) => {{
+        let mut res = Distribution::empty();
+        for ($v, p) in $d.outcomes {
+            res.add($f, p);
+        }
+        crate::util::yield_point().await;
+        res
+    }};
+}
+
+/// Applies `$f` to every pair of outcomes of `$l` and `$r`, which must be independent.
+macro_rules! combine_dist {
+    ($l:expr, $r:expr, |$a:ident, $b:ident| $f:expr) => {{
+        let (l, r) = ($l, $r);
+        let mut res = Distribution::empty();
+        for ($a, p) in &l.outcomes {
+            for ($b, q) in &r.outcomes {
+                let ($a, $b) = ($a.clone(), $b.clone());
+                res.add($f, Rational::from(p * q));
+            }
+            crate::util::yield_point().await;
+        }
+        res
+    }};
+}
+
+/// `a, b`, on values that have already been rolled.
+fn comma(a: RRVal, b: RRVal) -> RRVal {
+    match (a, b) {
+        (RRVal::Array(mut a), RRVal::Array(mut b)) => {
+            a.append(&mut b);
+            RRVal::Array(a)
+        }
+        (RRVal::Array(mut a), v) => {
+            a.push(v);
+            RRVal::Array(a)
+        }
+        (v, RRVal::Array(mut a)) => {
+            a.insert(0, v);
+            RRVal::Array(a)
+        }
+        (v, w) => RRVal::Array(vec![v, w]),
+    }
+}
+
+async fn add_to(sum: Option<RRVal>, v: RRVal) -> RRVal {
+    match sum {
+        Some(s) => s.add(v).await,
+        None => v,
+    }
+}
+
+/// The distribution of a single roll of one die, after rerolling.
+fn face_distribution(dice: &LazyDice) -> Distribution {
+    let weights = dice.side_weights();
+    let total: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+    let mut out = Distribution::empty();
+    // The probability that the die is still being rerolled.
+    let mut rerolling = Rational::from(1);
+    for rerolls in 0..=REROLL_LIMIT {
+        let mut next = Rational::new();
+        for (x, &w) in dice.sides.iter().zip(&weights) {
+            if w == 0 {
+                continue;
+            }
+            let p = Rational::from((w, total)) * &rerolling;
+            if (rerolls < REROLL_LIMIT && FaceMatch::any(&dice.reroll, x))
+                || (rerolls == 0 && FaceMatch::any(&dice.reroll_once, x))
+            {
+                next += p;
+            } else {
+                out.add(x.clone(), p);
+            }
+        }
+        if next == 0 {
+            break;
+        }
+        rerolling = next;
+    }
+    out
+}
+
+/// The joint distribution of the value of a single die, and of what it adds to the total: its
+/// number of successes if the dice count successes, or just its value otherwise.
+async fn die_distribution(
+    dice: &LazyDice,
+    explosion_limit: u32,
+) -> BTreeMap<(RRVal, RRVal), Rational> {
+    let faces = face_distribution(dice);
+    let mut stopped: BTreeMap<(RRVal, Option<RRVal>), Rational> = BTreeMap::new();
+    // Dice that are still exploding, by their value and successes so far.
+    let mut rolling = BTreeMap::from([((None, None), Rational::from(1))]);
+    for depth in 0..=explosion_limit {
+        let mut next = BTreeMap::new();
+        for ((value, score), p) in rolling {
+            for (x, q) in faces.outcomes() {
+                let face = if dice.explode_mode == ExplodeMode::Penetrate && depth > 0 {
+                    x.clone().sub(RRVal::from(1)).await
+                } else {
+                    x.clone()
+                };
+                let score = match &dice.successes {
+                    Some(s) if dice.explode_mode != ExplodeMode::Compound => {
+                        let face_score = RRVal::Int(s.score(&face).into());
+                        Some(add_to(score.clone(), face_score).await)
+                    }
+                    _ => score.clone(),
+                };
+                let value = add_to(value.clone(), face).await;
+                let p = Rational::from(&p * q);
+                if depth < explosion_limit && FaceMatch::any(&dice.explode, x) {
+                    *next.entry((Some(value), score)).or_default() += p;
+                } else {
+                    *stopped.entry((value, score)).or_default() += p;
+                }
+            }
+            crate::util::yield_point().await;
+        }
+        if next.is_empty() {
+            break;
+        }
+        rolling = next;
+    }
+    let mut res = BTreeMap::new();
+    for ((value, score), p) in stopped {
+        let score = match &dice.successes {
+            Some(s) if dice.explode_mode == ExplodeMode::Compound => {
+                RRVal::Int(s.score(&value).into())
+            }
+            Some(_) => score.unwrap_or(RRVal::Int(Integer::ZERO)),
+            None => value.clone(),
+        };
+        *res.entry((value, score)).or_default() += p;
+    }
+    res
+}
+
+/// The distribution of the total of a pool of dice.
+///
+/// If several dice in the pool tie for the last kept spot, rolling keeps whichever was rolled
+/// first. This breaks the tie by what the dice add to the total instead, which only makes a
+/// difference when counting successes on exploding dice.
+async fn dice_distribution(dice: LazyDice, explosion_limit: u32) -> Distribution {
+    if dice.sides.is_empty() || dice.kept_count() == 0 {
+        return Distribution::point(RRVal::Int(Integer::ZERO));
+    }
+    let die = die_distribution(&dice, explosion_limit).await;
+    let n = dice.num;
+    if dice.kept_count() == n {
+        // Nothing's dropped, so this is just a sum of independent dice.
+        let mut scores = Distribution::empty();
+        for ((_, score), p) in die {
+            scores.add(score, p);
+        }
+        let mut total = scores.clone();
+        for _ in 1..n {
+            total = combine_dist!(total, &scores, |a, b| a.add(b).await);
+        }
+        return total;
+    }
+    // Go through the possible values of a die in ascending order, and work out the probability of
+    // each number of dice showing it. A die is kept if its position in the sorted pool is between
+    // `lowest_idx` and `highest_idx`.
+    let mut pools = BTreeMap::from([((0, None), Rational::from(1))]);
+    for ((_, score), p) in die {
+        let mut next = BTreeMap::new();
+        for ((placed, total), w) in pools {
+            let mut p_k = Rational::from(1);
+            for k in 0..=(n - placed) {
+                let kept = (placed + k)
+                    .min(dice.highest_idx + 1)
+                    .saturating_sub(placed.max(dice.lowest_idx));
+                let mut total = total.clone();
+                for _ in 0..kept {
+                    total = Some(add_to(total, score.clone()).await);
+                }
+                let ways = Integer::from(Integer::binomial_u(n - placed, k));
+                *next.entry((placed + k, total)).or_default() += Rational::from(&w * &p_k) * ways;
+                p_k *= &p;
+            }
+            crate::util::yield_point().await;
+        }
+        pools = next;
+    }
+    let mut res = Distribution::empty();
+    for ((placed, total), p) in pools {
+        if placed == n {
+            res.add(total.unwrap_or(RRVal::Int(Integer::ZERO)), p);
+        }
+    }
+    res
+}
+
+/// The distribution of the kept dice of a pool, listed one by one like `faces(...)` lists them:
+/// in the order they were rolled, unless the pool is sorted.
+async fn faces_distribution(dice: LazyDice, explosion_limit: u32) -> anyhow::Result<Distribution> {
+    if dice.sides.is_empty() {
+        return Ok(Distribution::point(RRVal::Array(vec![])));
+    }
+    let mut die = Distribution::empty();
+    for ((value, _), p) in die_distribution(&dice, explosion_limit).await {
+        die.add(value, p);
+    }
+    let ways = u32::try_from(die.outcomes().len())
+        .ok()
+        .and_then(|faces| faces.checked_pow(dice.num))
+        .filter(|&ways| ways <= FACES_LIMIT);
+    if ways.is_none() {
+        anyhow::bail!(
+            "too many ways to roll {} dice to list their faces exactly: more than {FACES_LIMIT}",
+            dice.num
+        );
+    }
+    let mut pools = Distribution::point(RRVal::Array(vec![]));
+    for _ in 0..dice.num {
+        pools = combine_dist!(pools, &die, |a, b| match a {
+            RRVal::Array(mut a) => {
+                a.push(b);
+                RRVal::Array(a)
+            }
+            _ => unreachable!("only arrays are built up here"),
+        });
+    }
+    Ok(map_dist!(pools, |v| match v {
+        RRVal::Array(rolled) => RRVal::Array(kept_faces(rolled, &dice)),
+        _ => unreachable!("only arrays are built up here"),
+    }))
+}
+
+/// The dice out of `rolled` that `dice` keeps, in the order it lists them.
+fn kept_faces(rolled: Vec<RRVal>, dice: &LazyDice) -> Vec<RRVal> {
+    let mut order: Vec<_> = (0..rolled.len()).collect();
+    order.sort_by(|a, b| rolled[*a].cmp(&rolled[*b]));
+    let mut kept = vec![false; rolled.len()];
+    for i in order
+        .into_iter()
+        .skip(dice.lowest_idx as usize)
+        .take(dice.kept_count() as usize)
+    {
+        kept[i] = true;
+    }
+    let mut faces: Vec<_> = rolled
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(v, kept)| kept.then_some(v))
+        .collect();
+    match dice.sort {
+        Some(SortOrder::Ascending) => faces.sort(),
+        Some(SortOrder::Descending) => faces.sort_by(|a, b| b.cmp(a)),
+        None => (),
+    }
+    faces
+}
+
+/// A value partway through working out a distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DistValue {
+    /// Dice that modifiers like keep-highest can still be applied to.
+    Dice(LazyDice),
+    Dist(Distribution),
+}
+
+/// Works out the [`Distribution`] of an expression, treating every die in it as independent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistEvaluator {
+    explosion_limit: u32,
+    /// Variables that were already set when working out the distribution started.
+    /// They can't be changed, since that could make the dice depend on each other.
+    vars: BTreeMap<SmolStr, RRVal>,
+}
+
+impl DistEvaluator {
+    pub fn new(explosion_limit: u32) -> Self {
+        Self {
+            explosion_limit,
+            vars: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_vars(self, vars: BTreeMap<SmolStr, RRVal>) -> Self {
+        Self { vars, ..self }
+    }
+
+    pub async fn resolve(&mut self, v: DistValue) -> anyhow::Result<Distribution> {
+        Ok(match v {
+            DistValue::Dist(d) => d,
+            DistValue::Dice(dice) => dice_distribution(dice, self.explosion_limit).await,
+        })
+    }
+
+    /// Resolves `v`, which isn't allowed to be random.
+    async fn constant(&mut self, v: DistValue, what: &str) -> anyhow::Result<RRVal> {
+        match self.resolve(v).await?.as_point() {
+            Some(v) => Ok(v.clone()),
+            None => anyhow::bail!("{what} can't be random when working out probabilities"),
+        }
+    }
+
+    /// Checks the `N` in `NdM`, which has to be small enough to go through every die.
+    async fn dice_count(&mut self, num: Option<DistValue>) -> anyhow::Result<u32> {
+        let num = match num {
+            Some(num) => dice_count(self.constant(num, "the number of dice").await?.into())?,
+            None => 1,
+        };
+        if num > POOL_LIMIT {
+            anyhow::bail!("too many dice to work out exactly: {num} > {POOL_LIMIT}");
+        }
+        Ok(num)
+    }
+
+    async fn count(&mut self, v: DistValue, what: &str) -> anyhow::Result<u32> {
+        self.constant(v, what)
+            .await?
+            .into_i32()
+            .and_then(|v| v.try_into().map_err(|_| format!("is negative {v}")))
+            .map_err(|e| anyhow::anyhow!("invalid {what}: {e}"))
+    }
+
+    async fn matching_faces(
+        &mut self,
+        cond: Condition<DistValue>,
+        bare: Option<crate::dice::parse::Compare>,
+    ) -> anyhow::Result<Vec<FaceMatch>> {
+        let what = "the faces to apply a dice modifier to";
+        let cond = match cond {
+            Condition::Faces(v) => Condition::Faces(self.constant(v, what).await?),
+            Condition::Compare(cmp, v) => Condition::Compare(cmp, self.constant(v, what).await?),
+        };
+        Ok(FaceMatch::from_condition(cond, bare))
+    }
+
+    fn only_dice(v: DistValue, op: &str) -> anyhow::Result<LazyDice> {
+        match v {
+            DistValue::Dice(dice) => Ok(dice),
+            DistValue::Dist(_) => anyhow::bail!("{op} can only be applied to dice"),
+        }
+    }
+
+    async fn keep_or_drop(
+        &mut self,
+        dice: DistValue,
+        n: DistValue,
+        op: &str,
+        on_dice: fn(&mut LazyDice, u32),
+        on_array: fn(Vec<RRVal>, usize, bool) -> Vec<RRVal>,
+        lowest: bool,
+    ) -> anyhow::Result<DistValue> {
+        let n = self.count(n, &format!("{op} criterion")).await?;
+        match dice {
+            DistValue::Dice(mut dice) => {
+                on_dice(&mut dice, n);
+                Ok(DistValue::Dice(dice))
+            }
+            DistValue::Dist(d) => Ok(DistValue::Dist(map_dist!(d, |v| match v {
+                RRVal::Array(a) => RRVal::Array(on_array(a, n as usize, lowest)),
+                _ => anyhow::bail!("{op} operation is only valid on dice and arrays"),
+            }))),
+        }
+    }
+}
+
+impl ParseIns for DistEvaluator {
+    type Value = DistValue;
+
+    async fn literal<'t>(&self, v: Token<'t>) -> anyhow::Result<Self::Value> {
+        let v = match v {
+            Token::Number(x) => RRVal::Int(x.into()),
+            Token::Char(c) => RRVal::Char(c),
+            Token::Str(s) => RRVal::Array(s.chars().map(RRVal::Char).collect()),
+            Token::Ident(i) => match self.vars.get(i) {
+                Some(v) => v.clone(),
+                None => Err(ResolveError::undef_var(Place {
+                    varname: SmolStr::new(i),
+                    indexes: SmallVec::new(),
+                }))?,
+            },
+            Token::Eof => anyhow::bail!("incomplete expression".to_string()),
+            _ => anyhow::bail!("invalid literal `{}`", v),
+        };
+        Ok(DistValue::Dist(Distribution::point(v)))
+    }
+
+    async fn binop(
+        &mut self,
+        left: Self::Value,
+        right: Self::Value,
+        c: Op,
+    ) -> anyhow::Result<Self::Value> {
+        let (l, r) = (self.resolve(left).await?, self.resolve(right).await?);
+        Ok(DistValue::Dist(match c {
+            Op::Plus => combine_dist!(l, r, |a, b| a.add(b).await),
+            Op::Minus => combine_dist!(l, r, |a, b| a.sub(b).await),
+            Op::Star => combine_dist!(l, r, |a, b| a.mul(b).await),
+            Op::Slash => combine_dist!(l, r, |a, b| a.fdiv(b).await),
+            Op::Comma => combine_dist!(l, r, |a, b| comma(a, b)),
+            Op::Semicolon => r,
+            Op::Assign => {
+                anyhow::bail!("variables can't be set when working out probabilities".to_string())
+            }
+            Op::Equal => combine_dist!(l, r, |a, b| a.op_eq(b).await),
+            Op::Or => combine_dist!(l, r, |a, b| a.op_or(b).await),
+            Op::LAngle => combine_dist!(l, r, |a, b| a.op_lt(b).await),
+            Op::RAngle => combine_dist!(l, r, |a, b| a.op_gt(b).await),
+            Op::LAngleEq => combine_dist!(l, r, |a, b| a.op_le(b).await),
+            Op::RAngleEq => combine_dist!(l, r, |a, b| a.op_ge(b).await),
+            _ => anyhow::bail!("invalid infix operator `{}`", c.as_str()),
+        }))
+    }
+
+    async fn pfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
+        let d = self.resolve(inner).await?;
+        Ok(DistValue::Dist(match c {
+            Op::Plus => d,
+            Op::Minus => map_dist!(d, |v| v.neg().await),
+            Op::Comma => map_dist!(d, |v| RRVal::Array(vec![v])),
+            Op::Hash => map_dist!(d, |v| match v {
+                RRVal::Array(a) => RRVal::Int(a.len().into()),
+                _ => anyhow::bail!("cannot apply length operator (`#`) to non-array"),
+            }),
+            _ => anyhow::bail!("invalid prefix operator `{}`", c.as_str()),
+        }))
+    }
+
+    async fn sfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
+        let d = self.resolve(inner).await?;
+        Ok(DistValue::Dist(match c {
+            Op::Percent => map_dist!(d, |v| v.fdiv(100.into()).await),
+            _ => anyhow::bail!("invalid suffix operator `{}`", c.as_str()),
+        }))
+    }
+
+    async fn explode_suffix(
+        &mut self,
+        inner: Self::Value,
+        mode: ExplodeMode,
+        cond: Option<Condition<Self::Value>>,
+    ) -> anyhow::Result<Self::Value> {
+        let mut dice = Self::only_dice(inner, "`!`")?;
+        let faces = match cond {
+            Some(cond) => Some(self.matching_faces(cond, None).await?),
+            None => None,
+        };
+        dice.explode_on(faces, mode);
+        Ok(DistValue::Dice(dice))
+    }
+
+    async fn dice(
+        &mut self,
+        num: Option<Self::Value>,
+        sides: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let num = self.dice_count(num).await?;
+        if num == 0 {
+            return Ok(DistValue::Dist(Distribution::point(RRVal::Int(
+                Integer::ZERO,
+            ))));
+        }
+        let sides = match self.constant(sides, "the sides of a die").await? {
+            RRVal::Array(a) => a,
+            v => numbered_sides(v.into())?,
+        };
+        Ok(DistValue::Dice(LazyDice::new(num, sides)))
+    }
+
+    async fn weighted_dice(
+        &mut self,
+        num: Option<Self::Value>,
+        sides: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value> {
+        let num = self.dice_count(num).await?;
+        let mut faces = Vec::new();
+        let mut weights = Vec::new();
+        for (face, weight) in sides {
+            faces.push(self.constant(face, "the sides of a die").await?);
+            weights.push(self.constant(weight, "the weight of a side").await?);
+        }
+        let weights = side_weights(weights)?;
+        if num == 0 {
+            return Ok(DistValue::Dist(Distribution::point(RRVal::Int(
+                Integer::ZERO,
+            ))));
+        }
+        Ok(DistValue::Dice(LazyDice::weighted(num, faces, weights)))
+    }
+
+    async fn keep_highest(
+        &mut self,
+        dice: Self::Value,
+        keep: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        self.keep_or_drop(
+            dice,
+            keep,
+            "keep-highest",
+            LazyDice::keep_highest,
+            array_keep,
+            false,
+        )
+        .await
+    }
+
+    async fn keep_lowest(
+        &mut self,
+        dice: Self::Value,
+        keep: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        self.keep_or_drop(
+            dice,
+            keep,
+            "keep-lowest",
+            LazyDice::keep_lowest,
+            array_keep,
+            true,
+        )
+        .await
+    }
+
+    async fn drop_highest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        self.keep_or_drop(
+            dice,
+            drop,
+            "drop-highest",
+            LazyDice::drop_highest,
+            array_drop,
+            false,
+        )
+        .await
+    }
+
+    async fn drop_lowest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        self.keep_or_drop(
+            dice,
+            drop,
+            "drop-lowest",
+            LazyDice::drop_lowest,
+            array_drop,
+            true,
+        )
+        .await
+    }
+
+    async fn explode(
+        &mut self,
+        dice: Self::Value,
+        inner: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let mut dice = Self::only_dice(dice, "`!(...)!`")?;
+        let mut res = self.matching_faces(Condition::Faces(inner), None).await?;
+        dice.explode.append(&mut res);
+        Ok(DistValue::Dice(dice))
+    }
+
+    async fn reroll(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        once: bool,
+    ) -> anyhow::Result<Self::Value> {
+        let mut dice = Self::only_dice(dice, "rerolling")?;
+        let mut res = self.matching_faces(cond, None).await?;
+        if once {
+            dice.reroll_once.append(&mut res);
+        } else {
+            dice.reroll.append(&mut res);
+        }
+        Ok(DistValue::Dice(dice))
+    }
+
+    async fn tally(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        tally: Tally,
+    ) -> anyhow::Result<Self::Value> {
+        let mut dice = Self::only_dice(dice, "success counting")?;
+        let res = self.matching_faces(cond, tally.bare()).await?;
+        dice.tally(tally, res);
+        Ok(DistValue::Dice(dice))
+    }
+
+    async fn best(
+        &mut self,
+        pools: Vec<Self::Value>,
+        highest: bool,
+    ) -> anyhow::Result<Self::Value> {
+        let mut best: Option<Distribution> = None;
+        for pool in pools {
+            let d = self.resolve(pool).await?;
+            best = Some(match best {
+                Some(b) => combine_dist!(b, d, |a, b| pick_best(a, b, highest)),
+                None => d,
+            });
+        }
+        Ok(DistValue::Dist(
+            best.expect("there's always at least one pool"),
+        ))
+    }
+
+    async fn unique(&mut self, _dice: Self::Value) -> anyhow::Result<Self::Value> {
+        anyhow::bail!("dice that can't repeat a face can't be worked out exactly yet".to_string())
+    }
+
+    async fn sort(&mut self, dice: Self::Value, order: SortOrder) -> anyhow::Result<Self::Value> {
+        match dice {
+            // the order of the dice doesn't change their total
+            DistValue::Dice(mut dice) => {
+                dice.sort = Some(order);
+                Ok(DistValue::Dice(dice))
+            }
+            DistValue::Dist(d) => Ok(DistValue::Dist(map_dist!(d, |v| sort_array(v, order)?))),
+        }
+    }
+
+    async fn faces(&mut self, dice: Self::Value) -> anyhow::Result<Self::Value> {
+        let dice = Self::only_dice(dice, "listing faces")?;
+        Ok(DistValue::Dist(
+            faces_distribution(dice, self.explosion_limit).await?,
+        ))
+    }
+
+    async fn check(
+        &mut self,
+        roll: Self::Value,
+        skill: Self::Value,
+        _rolls_before: usize,
+    ) -> anyhow::Result<Self::Value> {
+        let (roll, skill) = (self.resolve(roll).await?, self.resolve(skill).await?);
+        Ok(DistValue::Dist(combine_dist!(roll, skill, |r, s| {
+            check_degree(r, s)?
+        })))
+    }
+
+    async fn repeat_count(&mut self, count: Self::Value) -> anyhow::Result<u32> {
+        repeat_count(
+            self.constant(count, "the number of repetitions")
+                .await?
+                .into(),
+        )
+    }
+
+    async fn bag(
+        &mut self,
+        entries: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value> {
+        let mut resolved = Vec::new();
+        for (symbol, count) in entries {
+            let symbol = self.constant(symbol, "a symbol").await?;
+            let count = self.constant(count, "the count of a symbol").await?;
+            resolved.push((symbol, count));
+        }
+        Ok(DistValue::Dist(Distribution::point(make_bag(resolved)?)))
+    }
+
+    async fn cancel(
+        &mut self,
+        bag: Self::Value,
+        a: Self::Value,
+        b: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let bag = self.resolve(bag).await?;
+        let a = self.constant(a, "a symbol").await?;
+        let b = self.constant(b, "a symbol").await?;
+        Ok(DistValue::Dist(map_dist!(bag, |v| {
+            cancel_symbols(v, a.clone(), b.clone())?
+        })))
+    }
+
+    async fn value(&mut self, v: RRVal) -> anyhow::Result<Self::Value> {
+        Ok(DistValue::Dist(Distribution::point(v)))
+    }
+
+    async fn draw(&mut self, _count: Self::Value) -> anyhow::Result<Self::Value> {
+        anyhow::bail!("drawing cards can't be worked out exactly".to_string())
+    }
+
+    async fn stat(&mut self, stat: Stat, dist: Distribution) -> anyhow::Result<Self::Value> {
+        Ok(DistValue::Dist(Distribution::point(RRVal::Float(
+            dist.stat(stat)?,
+        ))))
+    }
+
+    fn rolls_made(&self) -> usize {
+        0
+    }
+
+    fn dist_evaluator(&self) -> DistEvaluator {
+        self.clone()
+    }
+
+    async fn mk_array(&mut self, arr: Vec<Self::Value>) -> anyhow::Result<Self::Value> {
+        let mut res = Distribution::point(RRVal::Array(vec![]));
+        for v in arr {
+            let d = self.resolve(v).await?;
+            res = combine_dist!(res, d, |a, b| match a {
+                RRVal::Array(mut a) => {
+                    a.push(b);
+                    RRVal::Array(a)
+                }
+                _ => unreachable!("only arrays are built up here"),
+            });
+        }
+        Ok(DistValue::Dist(res))
+    }
+}
+
+/// Works out the exact distribution of the expression `s`, without rolling anything.
+pub async fn distribution(s: &str) -> anyhow::Result<Distribution> {
+    let (mut ins, val) = run_parser(s, DistEvaluator::new(DIST_EXPLOSION_LIMIT)).await?;
+    ins.resolve(val).await
+}
+
+#[tokio::test]
+async fn distribution_test() {
+    async fn p(s: &str, v: i32) -> Rational {
+        distribution(s).await.unwrap().probability(&RRVal::from(v))
+    }
+    fn r(n: i32, d: u32) -> Rational {
+        Rational::from((n, d))
+    }
+
+    assert_eq!(p("2d6", 7).await, r(1, 6));
+    assert_eq!(p("2d6", 13).await, r(0, 1));
+    assert_eq!(p("d6+d6", 12).await, r(1, 36));
+    assert_eq!(p("3d6-3", 0).await, r(1, 216));
+    assert_eq!(p("2*d4", 8).await, r(1, 4));
+    assert_eq!(p("2d20kh1", 20).await, r(39, 400));
+    assert_eq!(p("2d20kl1", 20).await, r(1, 400));
+    assert_eq!(p("2d20dl1", 20).await, r(39, 400));
+    assert_eq!(p("2d20kh1+5 >= 18", 1).await, r(16, 25));
+    assert_eq!(p("4d6kh3", 18).await, r(21, 1296));
+    assert_eq!(p("4d6kh3", 3).await, r(1, 1296));
+    assert_eq!(p("4d6dh1", 3).await, r(21, 1296));
+    assert_eq!(p("d6!", 6).await, r(0, 1));
+    assert_eq!(p("d6!", 7).await, r(1, 36));
+    assert_eq!(p("d6!p", 6).await, r(1, 36));
+    assert_eq!(p("d6ro1", 1).await, r(1, 36));
+    assert_eq!(p("d6ro1", 2).await, r(7, 36));
+    assert_eq!(p("3d10s8", 3).await, r(27, 1000));
+    assert_eq!(p("2d10s8f1", 0).await, r(21, 50));
+    assert_eq!(p("dF", 0).await, r(1, 3));
+    assert_eq!(p("dF.1", 0).await, r(2, 3));
+    assert_eq!(p("d%", 100).await, r(1, 100));
+    assert_eq!(p("d[1,1,2]", 1).await, r(2, 3));
+    assert_eq!(p("d{1: 3, 2: 1}", 1).await, r(3, 4));
+    assert_eq!(p("2d{1: 3, 2: 1}", 4).await, r(1, 16));
+    assert_eq!(p("d{1: 0, 2: 1}r2", 2).await, r(1, 1));
+    assert_eq!(p("2d{1: 1, 2: 2, 3: 7}kh1", 3).await, r(91, 100));
+    assert_eq!(p("0d6", 0).await, r(1, 1));
+    assert_eq!(p("2d6s", 7).await, r(1, 6));
+    assert_eq!(p("3d6s>4", 3).await, r(1, 27));
+    assert_eq!(
+        distribution("[2, 1, 3] sd")
+            .await
+            .unwrap()
+            .probability(&vec![3, 2, 1].into()),
+        r(1, 1)
+    );
+    assert!(distribution("4d4u").await.is_err());
+    assert_eq!(p("max(d6, d6)", 6).await, r(11, 36));
+    assert_eq!(p("min(d6, d6, d6)", 6).await, r(1, 216));
+    // a d4! can't stop on a 4, so only the wild die can
+    assert_eq!(p("wild(d4)", 4).await, r(1, 8));
+    assert_eq!(
+        p("wild(d4)", 5).await,
+        r(1, 16) * r(5, 6) + r(3, 4) * r(1, 6)
+    );
+    let faces = |v: &[i32]| RRVal::from(v.to_vec());
+    let d = distribution("faces(2d2)").await.unwrap();
+    assert_eq!(d.outcomes().len(), 4);
+    assert_eq!(d.probability(&faces(&[2, 1])), r(1, 4));
+    let d = distribution("faces(3d3dl1 sd)").await.unwrap();
+    assert_eq!(d.probability(&faces(&[3, 3])), r(7, 27));
+    assert_eq!(d.probability(&faces(&[2, 1])), r(3, 27));
+    let d = distribution("faces(2d3kh1)").await.unwrap();
+    assert_eq!(d.probability(&faces(&[3])), r(5, 9));
+    assert_eq!(p("#faces(4d6kl2)", 2).await, r(1, 1));
+    assert!(distribution("faces(3)").await.is_err());
+    // 6^6 ways to roll is too many to go through one by one
+    assert!(distribution("faces(5d6)").await.is_ok());
+    assert!(distribution("#faces(6d6)").await.is_err());
+    assert!(distribution("faces(3d100)").await.is_err());
+    let d = distribution("check(d100, 45)").await.unwrap();
+    assert_eq!(d.probability(&"critical success".into()), r(1, 100));
+    assert_eq!(d.probability(&"extreme success".into()), r(8, 100));
+    assert_eq!(d.probability(&"hard success".into()), r(13, 100));
+    assert_eq!(d.probability(&"success".into()), r(23, 100));
+    assert_eq!(d.probability(&"fumble".into()), r(5, 100));
+    assert_eq!(
+        distribution("2x(d2)")
+            .await
+            .unwrap()
+            .probability(&RRVal::Array(vec![1.into(), 2.into()])),
+        r(1, 4)
+    );
+
+    let d = distribution("3d6").await.unwrap();
+    assert_eq!(d.outcomes().len(), 16);
+    assert_eq!(d.outcomes().values().sum::<Rational>(), 1);
+    let d = distribution("5d6kh2!").await.unwrap();
+    assert_eq!(d.outcomes().values().sum::<Rational>(), 1);
+    let d = distribution("[d2, 3]").await.unwrap();
+    assert_eq!(d.probability(&RRVal::from(vec![2, 3])), r(1, 2));
+
+    let d = distribution("3d6").await.unwrap();
+    assert_eq!(d.mean().unwrap(), r(21, 2));
+    assert_eq!(d.variance().unwrap(), r(35, 4));
+    assert_eq!(d.median(), &RRVal::from(10));
+    assert_eq!(d.stat(Stat::StdDev).unwrap(), 8.75f64.sqrt());
+    let d = distribution("d6 > 4").await.unwrap();
+    assert_eq!(d.probability_true(), r(1, 3));
+    assert_eq!(d.stat(Stat::Median).unwrap(), 0.);
+    let d = distribution("d2 / 2").await.unwrap();
+    assert_eq!(d.mean().unwrap(), r(3, 4));
+    assert!(distribution("d\"ab\"").await.unwrap().mean().is_err());
+    let d = distribution("P(d2 == 2) + d2").await.unwrap();
+    assert_eq!(d.probability(&RRVal::Float(2.5)), r(1, 2));
+
+    let d = distribution("P(dSetback + dBoost == {})").await.unwrap();
+    assert_eq!(d.probability(&RRVal::Float(2. / 9.)), r(1, 1));
+    let d = distribution("dAbility + dDifficulty").await.unwrap();
+    assert_eq!(d.outcomes().values().sum::<Rational>(), 1);
+    let d = distribution("cancel({\"a\": 1}, \"a\", \"b\") + d{{\"b\": 1}: 1, {}: 1}")
+        .await
+        .unwrap();
+    assert_eq!(d.probability_true(), r(1, 2));
+    assert!(distribution("{\"a\": d2}").await.is_err());
+
+    assert!(distribution("x").await.is_err());
+    assert!(distribution("x = 3").await.is_err());
+    assert!(distribution("(d6)d6").await.is_err());
+    assert!(distribution("d6 kh d2").await.is_err());
+    assert!(distribution("3kh1").await.is_err());
+}
@@ -1,31 +1,133 @@
 use std::collections::{btree_map, BTreeMap};
 
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 use smallvec::SmallVec;
 use smol_str::SmolStr;
 
 use crate::dice::{
+    dist::{DistEvaluator, Distribution, Stat, DIST_EXPLOSION_LIMIT},
     lex::{Op, Token},
-    parse::ParseIns,
-    value::{LazyValue, RVal},
+    parse::{Compare, Condition, ParseIns, Tally},
+    value::{Bag, ExplodeMode, FaceMatch, LazyDice, LazyValue, RVal, SortOrder},
 };
 
 use super::{
-    value::{array_take_most_extreme_n, Place, RRVal, ResolveError},
+    deck::Deck,
+    random_seed,
+    value::{
+        array_drop, array_keep, resolve_dice, Crit, DiceRoll, DieRoll, Place, RRVal, ResolveError,
+    },
     vec_into,
 };
 
+/// What to do with a die that keeps exploding past [`EvalOptions::explosion_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnExplosionLimit {
+    /// Fail the whole evaluation.
+    #[default]
+    Error,
+    /// Stop exploding and keep the faces rolled so far.
+    Cap,
+}
+
+/// Knobs for embedders of the evaluator.
 #[derive(Debug, Clone, PartialEq)]
+pub struct EvalOptions {
+    /// How many times in a row a single die may explode.
+    pub explosion_limit: u32,
+    pub on_explosion_limit: OnExplosionLimit,
+    /// Seeds the dice, so the same expression with the same seed always rolls the same. A random
+    /// one is picked if this is `None`, and reported in [`Evaluation::seed`].
+    pub seed: Option<u64>,
+    /// How many of a d20's highest faces count as a critical success, e.g. 2 for crits on a 19 or
+    /// 20. 0 turns off crits, but a 1 is still a fumble. Other dice never crit.
+    pub crit_range: u32,
+    /// The deck that `draw(n)` draws cards from. What's left of it is in [`Evaluation::deck`].
+    pub deck: Option<Deck>,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            explosion_limit: 100,
+            on_explosion_limit: OnExplosionLimit::Error,
+            seed: None,
+            crit_range: 1,
+            deck: None,
+        }
+    }
+}
+
 pub struct Evaluator {
     vars: BTreeMap<SmolStr, RRVal>,
+    rolls: Vec<DiceRoll>,
+    options: EvalOptions,
+    /// `None` if the dice are rolled with an RNG from outside, rather than a seeded one.
+    seed: Option<u64>,
+    /// Every die in the evaluation draws from this, one after another.
+    rng: Box<dyn RngCore + Send + Sync>,
 }
 
 impl Evaluator {
-    fn new() -> Self {
+    fn new(options: EvalOptions) -> Self {
+        let seed = options.seed.unwrap_or_else(random_seed);
+        // a named algorithm, unlike `StdRng`, so a seed rolls the same across rand versions
+        let mut evaluator = Self::with_rng(options, ChaCha12Rng::seed_from_u64(seed));
+        evaluator.seed = Some(seed);
+        evaluator
+    }
+
+    fn with_rng(options: EvalOptions, rng: impl RngCore + Send + Sync + 'static) -> Self {
         Self {
             vars: Default::default(),
+            rolls: Vec::new(),
+            options,
+            seed: None,
+            rng: Box::new(rng),
         }
     }
 
+    pub fn options(&self) -> &EvalOptions {
+        &self.options
+    }
+
+    /// Rolls `dice`, keeping track of the roll for the final [`Evaluation`], and returns its
+    /// total.
+    pub async fn roll(&mut self, dice: LazyDice) -> Result<RRVal, ResolveError> {
+        Ok(self.roll_pool(dice).await?.total.clone())
+    }
+
+    /// Rolls `dice`, like [`Evaluator::roll`], but gives back each kept die rather than the total.
+    pub async fn roll_faces(&mut self, dice: LazyDice) -> anyhow::Result<Vec<RRVal>> {
+        let roll = self.roll_pool(dice).await?;
+        if roll.unlisted > 0 {
+            anyhow::bail!("too many dice to list their faces: {}", roll.unlisted);
+        }
+        Ok(roll.kept().map(|d| d.value.clone()).collect())
+    }
+
+    async fn roll_pool(&mut self, dice: LazyDice) -> Result<&DiceRoll, ResolveError> {
+        // boxed, since otherwise everything that might roll dice carries all of the state of
+        // rolling them, all the way up through the parser's recursion
+        let roll = Box::pin(resolve_dice(dice, &self.options, &mut self.rng)).await?;
+        self.rolls.push(roll);
+        Ok(self.rolls.last().unwrap())
+    }
+
+    /// Resolves `cond` and works out which faces it picks out. See [`FaceMatch::from_condition`].
+    async fn matching_faces(
+        &mut self,
+        cond: Condition<LazyValue>,
+        bare: Option<Compare>,
+    ) -> anyhow::Result<Vec<FaceMatch>> {
+        let cond = match cond {
+            Condition::Faces(v) => Condition::Faces(v.deep_resolve(self).await?),
+            Condition::Compare(cmp, v) => Condition::Compare(cmp, v.deep_resolve(self).await?),
+        };
+        Ok(FaceMatch::from_condition(cond, bare))
+    }
+
     pub fn var_get<'s>(&'s self, place: &Place) -> Result<&'s RRVal, ResolveError> {
         let mut placeref = self
             .vars
@@ -71,6 +173,180 @@ impl Evaluator {
     }
 }
 
+const DICE_LIMIT_SIDES: u32 = 65535;
+/// Pools past [`POOL_LIMIT`](super::value::POOL_LIMIT) dice are sampled in one go, so this only
+/// keeps totals sane.
+const DICE_LIMIT_COUNT: u32 = 1_000_000_000;
+
+/// Checks the `N` in `NdM`.
+pub(crate) fn dice_count(num: RVal) -> anyhow::Result<u32> {
+    num.into_i32()
+        .and_then(|v| u32::try_from(v).map_err(|_| "negative number of dice".to_string()))
+        .and_then(|v| {
+            if v > DICE_LIMIT_COUNT {
+                Err(format!("too many dice: {v} > {DICE_LIMIT_COUNT}"))
+            } else {
+                Ok(v)
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("invalid number of dice: {:?}", e))
+}
+
+/// The sides `1..=M` of a `dM` die.
+pub(crate) fn numbered_sides(sides: RVal) -> anyhow::Result<Vec<RRVal>> {
+    let sides_num: u32 = sides
+        .into_i32()
+        .and_then(|v| u32::try_from(v).map_err(|_| "negative number of sides".to_string()))
+        .map_err(|e| anyhow::anyhow!("invalid number of sides: {:?}", e))
+        .and_then(|v| {
+            if v > DICE_LIMIT_SIDES {
+                Err(anyhow::anyhow!("too many sides: {v} > {DICE_LIMIT_SIDES}"))
+            } else {
+                Ok(v)
+            }
+        })?;
+    Ok((1..=sides_num).map(|y| RRVal::Int(y.into())).collect())
+}
+
+/// Whichever of `a` and `b` that `max(...)` picks, or `min(...)` if not `highest`.
+pub(crate) fn pick_best(a: RRVal, b: RRVal, highest: bool) -> RRVal {
+    if (a >= b) == highest {
+        a
+    } else {
+        b
+    }
+}
+
+/// Sorts an array for `s`, `sa` and `sd`.
+pub(crate) fn sort_array(v: RRVal, order: SortOrder) -> anyhow::Result<RRVal> {
+    let RRVal::Array(mut a) = v else {
+        anyhow::bail!("only dice and arrays can be sorted".to_string());
+    };
+    match order {
+        SortOrder::Ascending => a.sort(),
+        SortOrder::Descending => a.sort_by(|x, y| y.cmp(x)),
+    }
+    Ok(RRVal::Array(a))
+}
+
+/// The degree of success of rolling `roll` on a d100 against `skill`, where lower is better, as in
+/// Call of Cthulhu.
+pub(crate) fn check_degree(roll: RRVal, skill: RRVal) -> anyhow::Result<RRVal> {
+    let (roll, skill) = match (roll.into_i32(), skill.into_i32()) {
+        (Ok(roll), Ok(skill)) => (roll, skill),
+        (Err(e), _) | (_, Err(e)) => anyhow::bail!("invalid check: {e}"),
+    };
+    let degree = if roll == 1 {
+        "critical success"
+    } else if roll >= 100 || (skill < 50 && roll >= 96) {
+        "fumble"
+    } else if roll <= skill / 5 {
+        "extreme success"
+    } else if roll <= skill / 2 {
+        "hard success"
+    } else if roll <= skill {
+        "success"
+    } else {
+        "failure"
+    };
+    Ok(degree.into())
+}
+
+/// The most times `Nx(...)` or `repeat(N, ...)` can repeat an expression, and the most
+/// repetitions a whole roll can have, counting nested ones.
+pub(crate) const REPEAT_LIMIT: u32 = 1000;
+
+/// Checks the `N` in `Nx(...)` and `repeat(N, ...)`.
+pub(crate) fn repeat_count(count: RVal) -> anyhow::Result<u32> {
+    count
+        .into_i32()
+        .and_then(|v| {
+            u32::try_from(v)
+                .ok()
+                .filter(|v| (1..=REPEAT_LIMIT).contains(v))
+                .ok_or_else(|| format!("{v} isn't between 1 and {REPEAT_LIMIT}"))
+        })
+        .map_err(|e| anyhow::anyhow!("invalid number of repetitions: {e}"))
+}
+
+/// Checks the `N` in `draw(N)`.
+fn card_count(count: RVal) -> anyhow::Result<usize> {
+    count
+        .into_i32()
+        .and_then(|v| usize::try_from(v).map_err(|_| format!("is negative {v}")))
+        .map_err(|e| anyhow::anyhow!("invalid number of cards: {e}"))
+}
+
+/// Checks the weights in `d{face: weight, ...}`.
+pub(crate) fn side_weights(weights: Vec<RRVal>) -> anyhow::Result<Vec<u32>> {
+    if weights.len() > DICE_LIMIT_SIDES as usize {
+        anyhow::bail!(format!(
+            "too many sides: {} > {DICE_LIMIT_SIDES}",
+            weights.len()
+        ));
+    }
+    let weights = weights
+        .into_iter()
+        .map(|w| {
+            RVal::from(w)
+                .into_i32()
+                .and_then(|v| u32::try_from(v).map_err(|_| format!("is negative {v}")))
+                .map_err(|e| anyhow::anyhow!("invalid weight of a side: {e}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if weights.iter().all(|&w| w == 0) {
+        anyhow::bail!("weighted dice need a side with a weight above 0".to_string());
+    }
+    // the weights are summed up as `u32`s when rolling
+    let total: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+    if total > u64::from(u32::MAX) {
+        anyhow::bail!(
+            "the weights of the sides add up to {total}, more than {}",
+            u32::MAX
+        );
+    }
+    Ok(weights)
+}
+
+/// Checks the name of a symbol in a bag, which has to be a string like `"success"`.
+fn symbol_name(symbol: RRVal) -> anyhow::Result<SmolStr> {
+    let name = match symbol {
+        RRVal::Array(a) if !a.is_empty() => a
+            .into_iter()
+            .map(|c| match c {
+                RRVal::Char(c) => Some(c),
+                _ => None,
+            })
+            .collect::<Option<String>>(),
+        _ => None,
+    };
+    name.map(SmolStr::from)
+        .ok_or_else(|| anyhow::anyhow!("symbols have to be strings, like \"success\""))
+}
+
+/// Makes the bag of symbols `{symbol: count, ...}`.
+pub(crate) fn make_bag(entries: Vec<(RRVal, RRVal)>) -> anyhow::Result<RRVal> {
+    let symbols = entries
+        .into_iter()
+        .map(|(symbol, count)| {
+            let count = RVal::from(count)
+                .into_i32()
+                .and_then(|v| u32::try_from(v).map_err(|_| format!("is negative {v}")))
+                .map_err(|e| anyhow::anyhow!("invalid count of a symbol: {e}"))?;
+            Ok((symbol_name(symbol)?, count))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(RRVal::Bag(Bag::new(symbols)))
+}
+
+/// `bag`, with the symbols `a` and `b` cancelling each other out, for `cancel(bag, a, b)`.
+pub(crate) fn cancel_symbols(bag: RRVal, a: RRVal, b: RRVal) -> anyhow::Result<RRVal> {
+    let RRVal::Bag(bag) = bag else {
+        anyhow::bail!("only bags of symbols can cancel each other out".to_string());
+    };
+    Ok(RRVal::Bag(bag.cancelling(symbol_name(a)?, symbol_name(b)?)))
+}
+
 impl ParseIns for Evaluator {
     type Value = LazyValue;
 
@@ -145,11 +421,13 @@ impl ParseIns for Evaluator {
             }
             Op::LAngle => Ok(deepres!(left, op_lt, right)),
             Op::RAngle => Ok(deepres!(left, op_gt, right)),
+            Op::LAngleEq => Ok(deepres!(left, op_le, right)),
+            Op::RAngleEq => Ok(deepres!(left, op_ge, right)),
             _ => anyhow::bail!("invalid infix operator `{}`", c.as_str()),
         }
     }
 
-    async fn pfxop(&self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
+    async fn pfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
         match c {
             Op::Plus => Ok(inner),
             Op::Minus => Ok(inner.deep_resolve(self).await?.neg().await.into()),
@@ -159,8 +437,8 @@ impl ParseIns for Evaluator {
             }
             Op::Hash => {
                 // Array length.
-                match inner {
-                    LazyValue::Array(a) => Ok(LazyValue::Int(a.len().into())),
+                match inner.resolve(self).await? {
+                    RVal::Array(a) => Ok(LazyValue::Int(a.len().into())),
                     _ => anyhow::bail!("cannot apply length operator (`#`) to non-array"),
                 }
             }
@@ -168,7 +446,7 @@ impl ParseIns for Evaluator {
         }
     }
 
-    async fn sfxop(&self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
+    async fn sfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value> {
         match c {
             Op::Percent => Ok(inner
                 .deep_resolve(self)
@@ -176,70 +454,49 @@ impl ParseIns for Evaluator {
                 .fdiv(100.into())
                 .await
                 .into()),
-            Op::Bang => {
-                /* explode! */
-                if let LazyValue::LazyDice {
-                    num,
-                    sides,
-                    lowest_idx,
-                    highest_idx,
-                    mut explode,
-                } = inner
-                {
-                    let l = sides.len();
-                    return Ok(LazyValue::LazyDice {
-                        num,
-                        sides,
-                        lowest_idx,
-                        highest_idx,
-                        explode: {
-                            explode.push(RRVal::Int(l.into()));
-                            explode
-                        },
-                    });
-                }
-                match inner.resolve(self).await? {
-                    RVal::Int(_) => {
-                        anyhow::bail!("factorial isn't implemented yet, sorry :P".to_string())
-                    }
-                    RVal::Float(_) => {
-                        anyhow::bail!(
-                            "floating point factorial isn't implemented yet, sorry :P".to_string()
-                        )
-                    }
-                    RVal::Char(_) => anyhow::bail!("you can't explode a character".to_string()),
-                    RVal::Array(_) => {
-                        anyhow::bail!("the operator `!` is not defined on arrays yet".to_string())
-                    }
-                }
-            }
             _ => anyhow::bail!("invalid suffix operator `{}`", c.as_str()),
         }
     }
 
+    async fn explode_suffix(
+        &mut self,
+        inner: Self::Value,
+        mode: ExplodeMode,
+        cond: Option<Condition<Self::Value>>,
+    ) -> anyhow::Result<Self::Value> {
+        /* explode! */
+        if let LazyValue::LazyDice(mut dice) = inner {
+            let faces = match cond {
+                Some(cond) => Some(self.matching_faces(cond, None).await?),
+                None => None,
+            };
+            dice.explode_on(faces, mode);
+            return Ok(LazyValue::LazyDice(dice));
+        }
+        match inner.resolve(self).await? {
+            RVal::Int(_) => {
+                anyhow::bail!("factorial isn't implemented yet, sorry :P".to_string())
+            }
+            RVal::Float(_) => {
+                anyhow::bail!("floating point factorial isn't implemented yet, sorry :P".to_string())
+            }
+            RVal::Char(_) => anyhow::bail!("you can't explode a character".to_string()),
+            RVal::Bag(_) => anyhow::bail!("you can't explode a bag of symbols".to_string()),
+            RVal::Array(_) => {
+                anyhow::bail!("the operator `!` is not defined on arrays yet".to_string())
+            }
+        }
+    }
+
     async fn dice(
         &mut self,
         num: Option<Self::Value>,
         sides_raw: Self::Value,
     ) -> anyhow::Result<Self::Value> {
-        const DICE_LIMIT_SIDES: u32 = 65535;
-        // TODO: large dice optimization
         let num: u32 = match num {
-            Some(nv) => nv
-                .resolve(self)
-                .await?
-                .into_i32()
-                .and_then(|v| u32::try_from(v).map_err(|_| "negative number of dice".to_string()))
-                .and_then(|v| {
-                    if v > DICE_LIMIT_SIDES {
-                        Err(format!("too many dice: {v} > {DICE_LIMIT_SIDES}"))
-                    } else {
-                        Ok(v)
-                    }
-                }),
-            None => Ok(1),
-        }
-        .map_err(|e| anyhow::anyhow!("invalid number of dice: {:?}", e))?;
+            Some(nv) => dice_count(nv.resolve(self).await?)?,
+            None => 1,
+        };
         if num == 0 {
             return Ok(LazyValue::Int(rug::Integer::ZERO));
         }
@@ -247,28 +504,31 @@ impl ParseIns for Evaluator {
         if let LazyValue::Array(a) = sides_raw {
             sides = RRVal::deep_resolve_vec(a, self).await?;
         } else {
-            let sides_num: u32 = sides_raw
-                .resolve(self)
-                .await?
-                .into_i32()
-                .and_then(|v| u32::try_from(v).map_err(|_| "negative number of sides".to_string()))
-                .map_err(|e| anyhow::anyhow!("invalid number of sides: {:?}", e))
-                .and_then(|v| {
-                    if v > DICE_LIMIT_SIDES {
-                        Err(anyhow::anyhow!("too many sides: {v} > {DICE_LIMIT_SIDES}"))
-                    } else {
-                        Ok(v)
-                    }
-                })?;
-            sides = (1..=sides_num).map(|y| RRVal::Int(y.into())).collect();
+            sides = numbered_sides(sides_raw.resolve(self).await?)?;
         }
-        Ok(LazyValue::LazyDice {
-            num,
-            sides,
-            lowest_idx: 0,
-            highest_idx: num - 1,
-            explode: vec![],
-        })
+        Ok(LazyValue::LazyDice(LazyDice::new(num, sides)))
+    }
+
+    async fn weighted_dice(
+        &mut self,
+        num: Option<Self::Value>,
+        sides: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value> {
+        let num: u32 = match num {
+            Some(nv) => dice_count(nv.resolve(self).await?)?,
+            None => 1,
+        };
+        let mut faces = Vec::new();
+        let mut weights = Vec::new();
+        for (face, weight) in sides {
+            faces.push(face.deep_resolve(self).await?);
+            weights.push(weight.deep_resolve(self).await?);
+        }
+        let weights = side_weights(weights)?;
+        if num == 0 {
+            return Ok(LazyValue::Int(rug::Integer::ZERO));
+        }
+        Ok(LazyValue::LazyDice(LazyDice::weighted(num, faces, weights)))
     }
 
     async fn keep_highest(
@@ -283,19 +543,10 @@ impl ParseIns for Evaluator {
             .and_then(|v| v.try_into().map_err(|_| format!("is negative {v}")))
             .map_err(|e| anyhow::anyhow!("invalid keep-highest criterion: {e}"))?;
         match dice {
-            LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                explode,
-            } => Ok(LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx: lowest_idx.max(highest_idx.saturating_sub(kh) + 1),
-                highest_idx,
-                explode,
-            }),
+            LazyValue::LazyDice(mut dice) => {
+                dice.keep_highest(kh);
+                Ok(LazyValue::LazyDice(dice))
+            }
             LazyValue::Int(_) => {
                 anyhow::bail!("keep-highest operation is invalid on integers".to_string())
             }
@@ -308,15 +559,12 @@ impl ParseIns for Evaluator {
             LazyValue::Char(_) => {
                 anyhow::bail!("keep-highest operation is invalid on characters".to_string())
             }
+            LazyValue::Bag(_) => {
+                anyhow::bail!("keep-highest operation is invalid on bags of symbols".to_string())
+            }
             LazyValue::Array(a) => {
                 let vals = RRVal::deep_resolve_vec(a, self).await?;
-                let new_vals = if kh == 0 {
-                    vec![]
-                } else if kh as usize >= vals.len() {
-                    vals
-                } else {
-                    array_take_most_extreme_n(vals, kh as usize, false)
-                };
+                let new_vals = array_keep(vals, kh as usize, false);
                 Ok(LazyValue::Array(vec_into(new_vals)))
             }
         }
@@ -334,19 +582,10 @@ impl ParseIns for Evaluator {
             .and_then(|v| v.try_into().map_err(|_| format!("is negative {v}")))
             .map_err(|e| anyhow::anyhow!("invalid keep-lowest criterion: {e}"))?;
         match dice {
-            LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                explode,
-            } => Ok(LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx: highest_idx.min((lowest_idx + kl).saturating_sub(1)),
-                explode,
-            }),
+            LazyValue::LazyDice(mut dice) => {
+                dice.keep_lowest(kl);
+                Ok(LazyValue::LazyDice(dice))
+            }
             LazyValue::Int(_) => {
                 anyhow::bail!("keep-lowest operation is invalid on integers".to_string())
             }
@@ -356,18 +595,93 @@ impl ParseIns for Evaluator {
             LazyValue::Char(_) => {
                 anyhow::bail!("keep-lowest operation is invalid on characters".to_string())
             }
+            LazyValue::Bag(_) => {
+                anyhow::bail!("keep-lowest operation is invalid on bags of symbols".to_string())
+            }
             LazyValue::Place(_) => {
                 anyhow::bail!("keep-lowest operation is invalid on variable references".to_string())
             }
             LazyValue::Array(a) => {
                 let vals = RRVal::deep_resolve_vec(a, self).await?;
-                let new_vals = if kl == 0 {
-                    vec![]
-                } else if kl as usize >= vals.len() {
-                    vals
-                } else {
-                    array_take_most_extreme_n(vals, kl as usize, true)
-                };
+                let new_vals = array_keep(vals, kl as usize, true);
+                Ok(LazyValue::Array(vec_into(new_vals)))
+            }
+        }
+    }
+
+    async fn drop_highest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let dh: u32 = drop
+            .resolve(self)
+            .await?
+            .into_i32()
+            .and_then(|v| v.try_into().map_err(|_| format!("is negative {v}")))
+            .map_err(|e| anyhow::anyhow!("invalid drop-highest criterion: {e}"))?;
+        match dice {
+            LazyValue::LazyDice(mut dice) => {
+                dice.drop_highest(dh);
+                Ok(LazyValue::LazyDice(dice))
+            }
+            LazyValue::Int(_) => {
+                anyhow::bail!("drop-highest operation is invalid on integers".to_string())
+            }
+            LazyValue::Float(_) => {
+                anyhow::bail!("drop-highest operation is invalid on numbers".to_string())
+            }
+            LazyValue::Char(_) => {
+                anyhow::bail!("drop-highest operation is invalid on characters".to_string())
+            }
+            LazyValue::Bag(_) => {
+                anyhow::bail!("drop-highest operation is invalid on bags of symbols".to_string())
+            }
+            LazyValue::Place(_) => {
+                anyhow::bail!("drop-highest operation is invalid on variable references".to_string())
+            }
+            LazyValue::Array(a) => {
+                let vals = RRVal::deep_resolve_vec(a, self).await?;
+                let new_vals = array_drop(vals, dh as usize, false);
+                Ok(LazyValue::Array(vec_into(new_vals)))
+            }
+        }
+    }
+
+    async fn drop_lowest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let dl: u32 = drop
+            .resolve(self)
+            .await?
+            .into_i32()
+            .and_then(|v| v.try_into().map_err(|_| format!("is negative {v}")))
+            .map_err(|e| anyhow::anyhow!("invalid drop-lowest criterion: {e}"))?;
+        match dice {
+            LazyValue::LazyDice(mut dice) => {
+                dice.drop_lowest(dl);
+                Ok(LazyValue::LazyDice(dice))
+            }
+            LazyValue::Int(_) => {
+                anyhow::bail!("drop-lowest operation is invalid on integers".to_string())
+            }
+            LazyValue::Float(_) => {
+                anyhow::bail!("drop-lowest operation is invalid on numbers".to_string())
+            }
+            LazyValue::Char(_) => {
+                anyhow::bail!("drop-lowest operation is invalid on characters".to_string())
+            }
+            LazyValue::Bag(_) => {
+                anyhow::bail!("drop-lowest operation is invalid on bags of symbols".to_string())
+            }
+            LazyValue::Place(_) => {
+                anyhow::bail!("drop-lowest operation is invalid on variable references".to_string())
+            }
+            LazyValue::Array(a) => {
+                let vals = RRVal::deep_resolve_vec(a, self).await?;
+                let new_vals = array_drop(vals, dl as usize, true);
                 Ok(LazyValue::Array(vec_into(new_vals)))
             }
         }
@@ -383,28 +697,181 @@ impl ParseIns for Evaluator {
             LazyValue::Float(_) => anyhow::bail!("cannot explode numbers".to_string()),
             LazyValue::Array(_) => anyhow::bail!("cannot explode arrays".to_string()),
             LazyValue::Char(_) => anyhow::bail!("cannot explode characters".to_string()),
+            LazyValue::Bag(_) => anyhow::bail!("cannot explode bags of symbols".to_string()),
             LazyValue::Place(_) => anyhow::bail!("cannot explode variable references".to_string()),
-            LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                mut explode,
-            } => Ok(LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                explode: {
-                    let mut res = match inner.deep_resolve(self).await? {
-                        RRVal::Array(a) => a,
-                        r => vec![r],
-                    };
-                    explode.append(&mut res);
-                    explode
-                },
-            }),
+            LazyValue::LazyDice(mut dice) => {
+                let mut res = self.matching_faces(Condition::Faces(inner), None).await?;
+                dice.explode.append(&mut res);
+                Ok(LazyValue::LazyDice(dice))
+            }
+        }
+    }
+
+    async fn reroll(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        once: bool,
+    ) -> anyhow::Result<Self::Value> {
+        match dice {
+            LazyValue::Int(_) => anyhow::bail!("cannot reroll integers".to_string()),
+            LazyValue::Float(_) => anyhow::bail!("cannot reroll numbers".to_string()),
+            LazyValue::Array(_) => anyhow::bail!("cannot reroll arrays".to_string()),
+            LazyValue::Char(_) => anyhow::bail!("cannot reroll characters".to_string()),
+            LazyValue::Bag(_) => anyhow::bail!("cannot reroll bags of symbols".to_string()),
+            LazyValue::Place(_) => anyhow::bail!("cannot reroll variable references".to_string()),
+            LazyValue::LazyDice(mut dice) => {
+                let mut res = self.matching_faces(cond, None).await?;
+                if once {
+                    dice.reroll_once.append(&mut res);
+                } else {
+                    dice.reroll.append(&mut res);
+                }
+                Ok(LazyValue::LazyDice(dice))
+            }
+        }
+    }
+
+    async fn tally(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        tally: Tally,
+    ) -> anyhow::Result<Self::Value> {
+        match dice {
+            LazyValue::Int(_) => anyhow::bail!("cannot count successes on integers".to_string()),
+            LazyValue::Float(_) => anyhow::bail!("cannot count successes on numbers".to_string()),
+            LazyValue::Array(_) => anyhow::bail!("cannot count successes on arrays".to_string()),
+            LazyValue::Char(_) => {
+                anyhow::bail!("cannot count successes on characters".to_string())
+            }
+            LazyValue::Bag(_) => {
+                anyhow::bail!("cannot count successes on bags of symbols".to_string())
+            }
+            LazyValue::Place(_) => {
+                anyhow::bail!("cannot count successes on variable references".to_string())
+            }
+            LazyValue::LazyDice(mut dice) => {
+                let res = self.matching_faces(cond, tally.bare()).await?;
+                dice.tally(tally, res);
+                Ok(LazyValue::LazyDice(dice))
+            }
+        }
+    }
+
+    async fn best(
+        &mut self,
+        pools: Vec<Self::Value>,
+        highest: bool,
+    ) -> anyhow::Result<Self::Value> {
+        let mut best = None;
+        for pool in pools {
+            let v = pool.deep_resolve(self).await?;
+            best = Some(match best {
+                Some(b) => pick_best(b, v, highest),
+                None => v,
+            });
+        }
+        Ok(best.expect("there's always at least one pool").into())
+    }
+
+    async fn unique(&mut self, dice: Self::Value) -> anyhow::Result<Self::Value> {
+        let LazyValue::LazyDice(mut dice) = dice else {
+            anyhow::bail!("only dice can be made unique".to_string());
+        };
+        dice.make_unique().map_err(|e| anyhow::anyhow!(e))?;
+        Ok(LazyValue::LazyDice(dice))
+    }
+
+    async fn sort(&mut self, dice: Self::Value, order: SortOrder) -> anyhow::Result<Self::Value> {
+        match dice {
+            LazyValue::LazyDice(mut dice) => {
+                dice.sort = Some(order);
+                Ok(LazyValue::LazyDice(dice))
+            }
+            v => Ok(sort_array(v.deep_resolve(self).await?, order)?.into()),
+        }
+    }
+
+    async fn faces(&mut self, dice: Self::Value) -> anyhow::Result<Self::Value> {
+        let LazyValue::LazyDice(dice) = dice else {
+            anyhow::bail!("only dice have faces to list".to_string());
+        };
+        Ok(RRVal::Array(self.roll_faces(dice).await?).into())
+    }
+
+    async fn check(
+        &mut self,
+        roll: Self::Value,
+        skill: Self::Value,
+        rolls_before: usize,
+    ) -> anyhow::Result<Self::Value> {
+        let roll = roll.deep_resolve(self).await?;
+        let skill = skill.deep_resolve(self).await?;
+        // the degree of success says how well the check went, not the dice
+        for die in self.rolls[rolls_before..]
+            .iter_mut()
+            .flat_map(|r| &mut r.dice)
+        {
+            die.crit = None;
         }
+        Ok(LazyValue::from(check_degree(roll, skill)?))
+    }
+
+    async fn repeat_count(&mut self, count: Self::Value) -> anyhow::Result<u32> {
+        repeat_count(count.resolve(self).await?)
+    }
+
+    async fn bag(
+        &mut self,
+        entries: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value> {
+        let mut resolved = Vec::new();
+        for (symbol, count) in entries {
+            resolved.push((
+                symbol.deep_resolve(self).await?,
+                count.deep_resolve(self).await?,
+            ));
+        }
+        Ok(make_bag(resolved)?.into())
+    }
+
+    async fn cancel(
+        &mut self,
+        bag: Self::Value,
+        a: Self::Value,
+        b: Self::Value,
+    ) -> anyhow::Result<Self::Value> {
+        let bag = bag.deep_resolve(self).await?;
+        let a = a.deep_resolve(self).await?;
+        let b = b.deep_resolve(self).await?;
+        Ok(cancel_symbols(bag, a, b)?.into())
+    }
+
+    async fn value(&mut self, v: RRVal) -> anyhow::Result<Self::Value> {
+        Ok(v.into())
+    }
+
+    async fn draw(&mut self, count: Self::Value) -> anyhow::Result<Self::Value> {
+        let n = card_count(count.resolve(self).await?)?;
+        let Some(deck) = &mut self.options.deck else {
+            anyhow::bail!("there's no deck of cards to draw from".to_string());
+        };
+        let cards = deck.draw(n)?;
+        Ok(RRVal::Array(cards.iter().map(|c| RRVal::from(c.as_str())).collect()).into())
+    }
+
+    async fn stat(&mut self, stat: Stat, dist: Distribution) -> anyhow::Result<Self::Value> {
+        Ok(LazyValue::Float(dist.stat(stat)?))
+    }
+
+    fn rolls_made(&self) -> usize {
+        self.rolls.len()
+    }
+
+    fn dist_evaluator(&self) -> DistEvaluator {
+        let limit = self.options.explosion_limit.min(DIST_EXPLOSION_LIMIT);
+        DistEvaluator::new(limit).with_vars(self.vars.clone())
     }
 
     async fn mk_array(&mut self, arr: Vec<Self::Value>) -> anyhow::Result<Self::Value> {
@@ -412,10 +879,77 @@ impl ParseIns for Evaluator {
     }
 }
 
-pub async fn eval(s: &str) -> anyhow::Result<RRVal> {
-    let (evaluator, val) = crate::dice::parse::run_parser(s, Evaluator::new()).await?;
-    let rrval = val.deep_resolve(&evaluator).await?;
-    Ok(rrval)
+/// The result of evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub value: RRVal,
+    /// Every dice roll made during evaluation, in the order they were made.
+    pub rolls: Vec<DiceRoll>,
+    /// The seed the dice were rolled with, which rolls them all the same again when passed in
+    /// [`EvalOptions::seed`], or `None` if they were rolled with [`eval_with_rng`].
+    pub seed: Option<u64>,
+    /// [`EvalOptions::deck`], after any cards were drawn from it.
+    pub deck: Option<Deck>,
+}
+
+impl Evaluation {
+    /// Renders the rolls like `[6, 5, ~~1~~, 3] [2]`, with dropped dice struck out.
+    pub fn breakdown(&self) -> String {
+        let mut s = String::new();
+        for roll in &self.rolls {
+            if !s.is_empty() {
+                s.push(' ');
+            }
+            s.push_str(&roll.to_string());
+        }
+        s
+    }
+
+    /// The kept dice whose natural roll was a critical success.
+    pub fn crits(&self) -> impl Iterator<Item = &DieRoll> {
+        self.kept_with(Crit::Success)
+    }
+
+    /// The kept dice whose natural roll was a fumble.
+    pub fn fumbles(&self) -> impl Iterator<Item = &DieRoll> {
+        self.kept_with(Crit::Fumble)
+    }
+
+    fn kept_with(&self, crit: Crit) -> impl Iterator<Item = &DieRoll> {
+        self.rolls
+            .iter()
+            .flat_map(DiceRoll::kept)
+            .filter(move |d| d.crit == Some(crit))
+    }
+}
+
+pub async fn eval(s: &str) -> anyhow::Result<Evaluation> {
+    eval_with(s, EvalOptions::default()).await
+}
+
+pub async fn eval_with(s: &str, options: EvalOptions) -> anyhow::Result<Evaluation> {
+    run(s, Evaluator::new(options)).await
+}
+
+/// Like [`eval_with`], but rolls every die with `rng`, e.g. a recorded sequence in tests or a
+/// CSPRNG. [`EvalOptions::seed`] is ignored.
+pub async fn eval_with_rng(
+    s: &str,
+    options: EvalOptions,
+    rng: impl RngCore + Send + Sync + 'static,
+) -> anyhow::Result<Evaluation> {
+    run(s, Evaluator::with_rng(options, rng)).await
+}
+
+async fn run(s: &str, evaluator: Evaluator) -> anyhow::Result<Evaluation> {
+    let (mut evaluator, val) = crate::dice::parse::run_parser(s, evaluator).await?;
+    let value = val.deep_resolve(&mut evaluator).await?;
+    Ok(Evaluation {
+        value,
+        rolls: evaluator.rolls,
+        seed: evaluator.seed,
+        deck: evaluator.options.deck,
+    })
 }
 
 #[tokio::test]
@@ -423,7 +957,7 @@ async fn eval_positive_test() {
     macro_rules! good {
         ($x:expr, $y:literal / $z:literal) => {
             let m;
-            if let RRVal::Float(m1) = eval($x).await.unwrap() {
+            if let RRVal::Float(m1) = eval($x).await.unwrap().value {
                 m = m1;
             } else {
                 panic!();
@@ -434,16 +968,16 @@ async fn eval_positive_test() {
             }
         };
         ($x:expr, $y:literal) => {
-            assert_eq!(eval($x).await.unwrap().into_i32().unwrap(), $y)
+            assert_eq!(eval($x).await.unwrap().value.into_i32().unwrap(), $y)
         };
         ($x:expr, [ $($e:expr),+ ]) => {
-            assert_eq!(eval($x).await.unwrap(), vec![$($e),*].into())
+            assert_eq!(eval($x).await.unwrap().value, vec![$($e),*].into())
         };
         ($x:expr, []) => {
-            assert_eq!(eval($x).await.unwrap(), RRVal::Array(vec![]))
+            assert_eq!(eval($x).await.unwrap().value, RRVal::Array(vec![]))
         };
         ($x:expr, #$y:expr) => {
-            assert_eq!(eval($x).await.unwrap(), $y.into())
+            assert_eq!(eval($x).await.unwrap().value, $y.into())
         };
     }
 
@@ -488,6 +1022,16 @@ async fn eval_positive_test() {
     good!("3d4KH2", 6);
     good!("3d4KL2", 4);
 
+    good!("4d1dl1", 3);
+    good!("4d1DH3", 1);
+    good!("4d1dl9", 0);
+    good!("4d1dh4", 0);
+    good!("4d1dl0", 4);
+    good!("3d4dl1", 6);
+    good!("6d1kh4dl1", 3);
+    good!("6d1kh4dh1dl1", 2);
+    good!("6d1dl1kh4", 4);
+
     good!("2d2!", 8);
     good!("2d2!(2)!", 8);
     good!("2d2!(,2)!", 8);
@@ -500,6 +1044,54 @@ async fn eval_positive_test() {
     good!("d4!", 3);
     good!("d4!(3)!", 7);
 
+    good!("d3!>2", 5);
+    good!("d3!>=3", 5);
+    good!("d3!==3", 5);
+    good!("4d1!>1", 4);
+    good!("d3!!", 5);
+    good!("d3!p", 4);
+    good!("d3!!s4", 1);
+    good!("d3!s4", 0);
+    good!("d3!s2", 2);
+    good!("d3!p>2", 4);
+    // with a space, the comparison is of the roll, not what explodes
+    good!("d3! > 2", 1);
+    good!("d3! >= 6", 0);
+    good!("d3!! == 3", 0);
+    good!("d3!p > 2", 1);
+
+    good!("d1r1", 1);
+    good!("10d2r1", 20);
+    good!("10d2r<2", 20);
+    good!("10d2r(1)", 20);
+    good!("10d2R[1]", 20);
+    good!("10d3r(1,2)", 30);
+    good!("10d2r==2", 10);
+    good!("4d1ro1", 4);
+
+    good!("8d1s1", 8);
+    good!("8d10s11", 0);
+    good!("10d10s1", 10);
+    good!("10d10s>10", 0);
+    good!("10d10S<=10", 10);
+    good!("10d10s(1,2,3,4,5,6,7,8,9,10)", 10);
+    good!("5d1s1f1", 0);
+    good!("5d1s1dbl1", 10);
+    good!("4d1f1", -4);
+    good!("3d1kh2s1", 2);
+    good!("3d1s1+1", 4);
+
+    good!("P(2d6 >= 8)", 15. / 36.);
+    good!("P(d{1: 7, 2: 2, 3: 1} == 3)", 1. / 10.);
+    good!("P(2d20kh1+5 >= 18)", 16. / 25.);
+    good!("E(4d6)", 14. / 1.);
+    good!("E(d4)*2", 5. / 1.);
+    good!("median(3d6)", 10. / 1.);
+    good!("stddev(d1)", 0. / 1.);
+    good!("x=5; P(d10 > x)", 1. / 2.);
+    good!("P(d4 == P(d2 == 1)*2)", 1. / 4.);
+    good!("E(d6!)*5+d1 > 21", 1);
+
     good!(",2", #vec![2]);
     good!("1,2", #vec![1,2]);
     good!("1,2,3", #vec![1,2,3]);
@@ -538,6 +1130,12 @@ async fn eval_positive_test() {
     good!("3 < 1", 0);
     good!("1 > 3", 0);
     good!("3 > 1", 1);
+    good!("1 <= 1", 1);
+    good!("2 <= 1", 0);
+    good!("1 >= 1", 1);
+    good!("0 >= 1", 0);
+    good!("1 <= [0, 1, 2]", [0, 1, 1]);
+    good!("1 >= [0, 1, 2]", [1, 1, 0]);
 
     good!("z=0/1; n=0/0; [z < n, z == n, z > n]", [0, 0, 1]);
     good!("n=0/0; [0 < n, 0 == n, 0 > n]", [0, 0, 1]);
@@ -552,33 +1150,539 @@ async fn eval_positive_test() {
     good!("[1,2,3,4] kh 0", []);
     good!("[1,2,3,4] kl 4", [1, 2, 3, 4]);
     good!("[1,2,3,4] kl 0", []);
+    good!("[3,1,4,2] dl 1", [2, 3, 4]);
+    good!("[3,1,4,2] dh 1", [1, 2, 3]);
+    good!("[3,1,4,2] dh 0", [3, 1, 4, 2]);
+    good!("[3,1,4,2] dl 4", []);
+    good!("[3,1,4,2] kh 3 dl 1", [3, 4]);
+
+    good!("d{7: 2000000000, 8: 2000000000} > 6", 1);
+
+    good!("4d4u", 10);
+    good!("3dF u", 0);
+    good!("faces(4d4u s)", [1, 2, 3, 4]);
+    good!("faces(4d4u sa)", [1, 2, 3, 4]);
+    good!("faces(4d4u sd)", [4, 3, 2, 1]);
+    good!("faces(2d{1: 1, 2: 0, 3: 1}u s)", [1, 3]);
+    good!("[3, 1, 2] s", [1, 2, 3]);
+    good!("[3, 1, 2] sd", [3, 2, 1]);
+    good!("x = [2, 3, 1]; x sa", [1, 2, 3]);
+    good!("3d1s + 1", 4);
+    good!("3d1s1", 3);
+    good!("3d1s(1, 2)", 3);
+
+    good!("faces(3d1)", [1, 1, 1]);
+    good!("#faces(4d6kh3)", 3);
+    good!("pool = faces(5d1); pool > 0", [1, 1, 1, 1, 1]);
+    good!("pool = faces(3d6); #pool", 3);
+    good!("faces(2d1) + [1, 2]", [2, 3]);
+    good!("faces(3d1) * 2", [2, 2, 2]);
+    good!("faces = 2; faces", 2);
+
+    good!("max(3, 5, 4)", 5);
+    good!("min(3, 5, 4)", 3);
+    good!("max(2)", 2);
+    good!("max(2d1, 3d1) + 1", 4);
+    good!("max([1, 2], [1, 3])", [1, 3]);
+    good!("max = 3; min = 4; max + min", 7);
+
+    good!("check(1, 10)", #"critical success");
+    good!("check(9, 45)", #"extreme success");
+    good!("check(22, 45)", #"hard success");
+    good!("check(45, 45)", #"success");
+    good!("check(46, 45)", #"failure");
+    good!("check(96, 45)", #"fumble");
+    good!("check(96, 50)", #"failure");
+    good!("check(100, 120)", #"fumble");
+    good!("check(50d1, 100d1)", #"hard success");
+    good!("check = 3; check + 1", 4);
+
+    good!("3x(2d1)", [2, 2, 2]);
+    good!("2x3d1kh2+1", [3, 3]);
+    good!("1+2x(d1)", [2, 2]);
+    good!("repeat(2, d1 + 1)", [2, 2]);
+    good!("#2x(3x(d1))", 2);
+    good!("repeat = 4; repeat", 4);
 
     // - fuzzing-based tests -
     good!("0d[5,6,7]", 0);
     // special NaN check lmao
-    let nanres = eval("30d(0/3,0/0,0)").await.unwrap();
+    let nanres = eval("30d(0/3,0/0,0)").await.unwrap().value;
     let RRVal::Float(nanres) = nanres else {
         panic!("non-NaN value {nanres}")
     };
     assert!(nanres.is_nan(), "non-NaN value {nanres}");
 }
 
+#[tokio::test]
+async fn eval_rolls_test() {
+    let res = eval("4d6kh3").await.unwrap();
+    assert_eq!(res.rolls.len(), 1);
+    let roll = &res.rolls[0];
+    assert_eq!(roll.dice.len(), 4);
+    assert_eq!(roll.kept().count(), 3);
+    let dropped = roll.dropped().next().unwrap();
+    assert!(roll.kept().all(|d| d.value >= dropped.value));
+    assert_eq!(roll.total, res.value);
+
+    let res = eval("d3!").await.unwrap();
+    assert_eq!(res.rolls.len(), 1);
+    let die = &res.rolls[0].dice[0];
+    assert!(die.exploded());
+    assert_eq!(die.faces, vec![RRVal::from(3), RRVal::from(2)]);
+    assert_eq!(res.breakdown(), "[3!+2]");
+
+    let res = eval("d2+3d1").await.unwrap();
+    assert_eq!(res.rolls.len(), 2);
+    assert_eq!(res.breakdown(), "[2] [1, 1, 1]");
+
+    let res = eval("d3!p").await.unwrap();
+    let die = &res.rolls[0].dice[0];
+    assert_eq!(die.faces, vec![RRVal::from(3), RRVal::from(1)]);
+    // `d20! > 15` compares the exploded roll with 15
+    for seed in 0..20 {
+        let options = EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let res = eval_with("d20! > 15", options).await.unwrap();
+        let total = res.rolls[0].total.clone();
+        assert_eq!(res.value, RRVal::from((total > RRVal::from(15)) as i32));
+    }
+
+    let res = eval("d1r1").await.unwrap();
+    let die = &res.rolls[0].dice[0];
+    assert_eq!(die.rerolled.len(), super::value::REROLL_LIMIT as usize);
+    let res = eval("d1ro1").await.unwrap();
+    assert_eq!(res.rolls[0].dice[0].rerolled, vec![RRVal::from(1)]);
+
+    let res = eval("4dF+dF.1").await.unwrap();
+    assert_eq!(res.rolls.len(), 2);
+    let faces = [-1, 0, 1].map(RRVal::from);
+    assert!(res
+        .rolls
+        .iter()
+        .all(|r| r.dice.iter().all(|d| faces.contains(&d.value))));
+    for seed in 0..20 {
+        let options = EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let res = eval_with("2d%", options).await.unwrap();
+        assert_eq!(res.rolls[0].dice.len(), 2);
+        assert!(res.value >= RRVal::from(2) && res.value <= RRVal::from(200));
+    }
+    let res = eval("100dF.1").await.unwrap();
+    let zeroes = res.rolls[0]
+        .dice
+        .iter()
+        .filter(|d| d.value == RRVal::from(0));
+    assert!(zeroes.count() > 40);
+
+    let res = eval("x=2d1; 3").await.unwrap();
+    assert_eq!(res.rolls.len(), 1);
+    assert!(eval("3+4").await.unwrap().rolls.is_empty());
+}
+
 #[tokio::test]
 async fn eval_negative_test() {
     macro_rules! bad {
         ($x:expr) => {
             let e = eval($x).await;
             if e.is_ok() {
-                panic!("no error thrown for {:?}", e.unwrap());
+                panic!("no error thrown for {:?}", e.unwrap().value);
             }
         };
     }
 
-    bad!("4810954d1093491"); // dice x > 65535
+    bad!("4810954d1093491"); // sides > 65535
     bad!("2 2");
     bad!("$2");
     bad!("2$");
     bad!("#2");
     bad!("2 = 2");
     bad!("x");
+    bad!("3r1");
+    bad!("[1,2]ro1");
+    bad!("3s1");
+    bad!("[1]f1");
+    bad!("dF.3");
+    bad!("P(x)");
+    bad!("P(x=2)");
+    bad!("E(d\"ab\")");
+    bad!("P(2d6");
+    bad!("P");
+    bad!("d{}");
+    bad!("d{1: 0}");
+    bad!("d{1: -1}");
+    bad!("d{1}");
+    bad!("d{1: 1");
+    bad!("d{1: 1, 2}");
+    bad!("d{1: x}");
+    bad!("d{1: 2000000000, 2: 2000000000, 3: 2000000000}");
+    bad!("5d4u");
+    bad!("3d{1: 1, 2: 0, 3: 1}u");
+    bad!("3u");
+    bad!("[1, 2]u");
+    bad!("'a' s");
+    bad!("faces(3)");
+    bad!("faces([1, 2])");
+    bad!("faces(100000d6)");
+    bad!("max()");
+    bad!("max(1, )");
+    bad!("wild(3)");
+    bad!("check(d100)");
+    bad!("check(d100, \"a\")");
+    bad!("check([1, 2], 50)");
+    bad!("0x(d6)");
+    bad!("1001x(d6)");
+    bad!("1000x(1000x(1000x(1)))");
+    bad!("100x(100x(d6))");
+    bad!("600x(d6) + 600x(d6)");
+    bad!("repeat(2)");
+    bad!("repeat(2, d6");
+}
+
+#[tokio::test]
+async fn eval_explosion_limit_test() {
+    let e = eval("d1!").await.unwrap_err();
+    assert_eq!(e.to_string(), "explosion limit 100 reached on die d1");
+    let e = eval("2+d[2,2]!").await.unwrap_err();
+    assert_eq!(e.to_string(), "explosion limit 100 reached on die d[2, 2]");
+
+    let capped = EvalOptions {
+        explosion_limit: 5,
+        on_explosion_limit: OnExplosionLimit::Cap,
+        ..Default::default()
+    };
+    let res = eval_with("d1!", capped.clone()).await.unwrap();
+    assert_eq!(res.value, RRVal::from(6));
+    assert_eq!(res.rolls[0].dice[0].faces.len(), 6);
+    let res = eval_with("2d1!!", capped).await.unwrap();
+    assert_eq!(res.value, RRVal::from(12));
+
+    let none = EvalOptions {
+        explosion_limit: 0,
+        ..Default::default()
+    };
+    assert!(eval_with("d1!", none.clone()).await.is_err());
+    assert_eq!(eval_with("d1", none).await.unwrap().value, RRVal::from(1));
+}
+
+#[tokio::test]
+async fn eval_seed_test() {
+    let seeded = |seed| EvalOptions {
+        seed: Some(seed),
+        ..Default::default()
+    };
+    let a = eval_with("10d20+d100", seeded(12345)).await.unwrap();
+    let b = eval_with("10d20+d100", seeded(12345)).await.unwrap();
+    assert_eq!(a.seed, Some(12345));
+    assert_eq!(a.value, b.value);
+    assert_eq!(a.rolls, b.rolls);
+    let c = eval_with("10d20+d100", seeded(54321)).await.unwrap();
+    assert_ne!(a.rolls, c.rolls);
+    // seeds from old rolls and receipts have to keep rolling the same
+    assert_eq!(a.breakdown(), "[7, 18, 11, 6, 9, 18, 13, 12, 5, 11] [23]");
+    assert_eq!(a.value, RRVal::from(133));
+
+    // every die in an evaluation draws from the same stream, rather than starting over
+    let res = eval_with("d60000 - d60000", seeded(1)).await.unwrap();
+    assert_ne!(res.value, RRVal::from(0));
+
+    let res = eval("d6").await.unwrap();
+    let again = eval_with("d6", seeded(res.seed.unwrap())).await.unwrap();
+    assert_eq!(res.rolls, again.rolls);
+}
+
+#[tokio::test]
+async fn eval_with_rng_test() {
+    use rand::rngs::mock::StepRng;
+
+    // always rolls the lowest face
+    let res = eval_with_rng("4d6+d[3,2,1]", EvalOptions::default(), StepRng::new(0, 0))
+        .await
+        .unwrap();
+    assert_eq!(res.value, RRVal::from(7));
+    assert_eq!(res.seed, None);
+
+    // a d1 explodes on every roll, whatever the RNG, so it always runs into the explosion limit
+    let e = eval_with_rng("d1!", EvalOptions::default(), StepRng::new(0, 0)).await;
+    assert!(e.is_err());
+}
+
+#[tokio::test]
+async fn eval_unique_sort_test() {
+    for seed in 0..20 {
+        let options = EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let res = eval_with("6d6u", options).await.unwrap();
+        assert_eq!(res.value, RRVal::from(21));
+        let mut seen: Vec<_> = res.rolls[0]
+            .dice
+            .iter()
+            .map(|d| d.faces[0].clone())
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 6);
+    }
+    let res = eval("10d100sd").await.unwrap();
+    let values: Vec<_> = res.rolls[0].dice.iter().map(|d| d.value.clone()).collect();
+    assert!(values.windows(2).all(|w| w[0] >= w[1]));
+    let res = eval("faces(10d100s)").await.unwrap();
+    let RRVal::Array(faces) = res.value else {
+        panic!("faces(...) should be an array");
+    };
+    assert!(faces.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(res.rolls.len(), 1);
+}
+
+#[tokio::test]
+async fn eval_wild_test() {
+    use rand::rngs::mock::StepRng;
+
+    for seed in 0..20 {
+        let options = EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let res = eval_with("wild(d8)", options).await.unwrap();
+        // both pools are rolled, and show up in the breakdown
+        assert_eq!(res.rolls.len(), 2);
+        let best = res.rolls.iter().map(|r| r.total.clone()).max().unwrap();
+        assert_eq!(res.value, best);
+    }
+    let res = eval("wild(d8) - 2").await.unwrap();
+    assert!(res.value >= RRVal::from(-1));
+
+    // the wild die is a d6, and both dice explode
+    let res = eval_with_rng("wild(d4)", EvalOptions::default(), StepRng::new(0, 0))
+        .await
+        .unwrap();
+    assert_eq!(res.breakdown(), "[1] [1]");
+    assert_eq!(res.rolls[1].dice[0].faces.len(), 1);
+}
+
+#[tokio::test]
+async fn eval_bag_test() {
+    async fn v(s: &str) -> RRVal {
+        eval(s).await.unwrap().value
+    }
+
+    assert_eq!(
+        v("{\"success\": 2} + {\"advantage\": 1}").await,
+        v("{\"advantage\": 1, \"success\": 2, \"threat\": 0}").await
+    );
+    // nothing cancels unless it's asked to
+    assert_eq!(
+        v("{\"success\": 1} + {\"failure\": 1}").await.to_string(),
+        "``{\"failure\": 1, \"success\": 1}``"
+    );
+    assert_eq!(
+        v("cancel({\"success\": 2}, \"success\", \"failure\") + {\"failure\": 3}").await,
+        v("{\"failure\": 1}").await
+    );
+    assert_eq!(v("3 * {\"threat\": 1}").await, v("{\"threat\": 3}").await);
+    for expr in [
+        "3 * {\"threat\": 1} + 2",
+        "1 + {\"a\": 1}",
+        "{\"a\": 1} - 5 / 2",
+    ] {
+        let RRVal::Float(res) = v(expr).await else {
+            panic!("{expr} should be NaN");
+        };
+        assert!(res.is_nan(), "{expr} should be NaN, not {res}");
+    }
+    assert_eq!(
+        v("{\"a\": 2} - {\"a\": 1, \"b\": 1}").await,
+        v("{\"a\": 1}").await
+    );
+    assert_eq!(v("{\"a\": 1} == {\"a\": 1}").await, RRVal::from(1));
+    assert!(!v("{}").await.truthy());
+
+    for seed in 0..20 {
+        let options = EvalOptions {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let pool = "2dAbility + dProficiency + 3dDifficulty + dSetback";
+        let RRVal::Bag(bag) = eval_with(pool, options).await.unwrap().value else {
+            panic!("narrative dice roll bags");
+        };
+        assert!(bag.count("success") == 0 || bag.count("failure") == 0);
+        assert!(bag.count("advantage") == 0 || bag.count("threat") == 0);
+    }
+    let res = eval("2dForce").await.unwrap();
+    assert_eq!(res.rolls[0].dice.len(), 2);
+
+    assert!(eval("{1: 2}").await.is_err());
+    assert!(eval("{\"a\": -1}").await.is_err());
+    assert!(eval("cancel(3, \"a\", \"b\")").await.is_err());
+    assert!(eval("dBoost kh1").await.is_ok());
+    assert!(eval("dBoost r1").await.is_ok());
+    assert!(eval("{\"a\": 1} kh1").await.is_err());
+}
+
+#[tokio::test]
+async fn eval_deck_test() {
+    let options = EvalOptions {
+        deck: Some(Deck::standard()),
+        ..Default::default()
+    };
+    let res = eval_with("hand = draw(2); [hand, draw()]", options.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        res.value,
+        RRVal::Array(vec![vec!["A♠", "2♠"].into(), vec!["3♠"].into()])
+    );
+    let deck = res.deck.unwrap();
+    assert_eq!(deck.remaining(), 49);
+    assert_eq!(deck.drawn(), ["A♠", "2♠", "3♠"]);
+
+    let res = eval_with("#draw(3) + #draw(0)", options.clone())
+        .await
+        .unwrap();
+    assert_eq!(res.value, RRVal::from(3));
+    assert!(eval_with("draw(53)", options.clone()).await.is_err());
+    assert!(eval_with("draw(-1)", options).await.is_err());
+    assert!(eval("draw(1)").await.is_err());
+    assert!(crate::dice::distribution("draw(1)").await.is_err());
+}
+
+#[tokio::test]
+async fn eval_crit_test() {
+    use rand::rngs::mock::StepRng;
+
+    // always rolls the first side listed
+    async fn first_side(s: &str, crit_range: u32) -> Evaluation {
+        let options = EvalOptions {
+            crit_range,
+            ..Default::default()
+        };
+        eval_with_rng(s, options, StepRng::new(0, 0)).await.unwrap()
+    }
+    let crits = |e: &Evaluation| e.crits().map(|d| d.faces[0].clone()).collect::<Vec<_>>();
+    let fumbles = |e: &Evaluation| e.fumbles().count();
+    // a d20 with `first` as its first side
+    let d20 = |first: i64| {
+        let rest = (1..=20).filter(|&n| n != first).map(|n| n.to_string());
+        let sides: Vec<_> = std::iter::once(first.to_string()).chain(rest).collect();
+        format!("d[{}]", sides.join(", "))
+    };
+
+    let res = first_side("d20", 1).await;
+    assert_eq!((crits(&res), fumbles(&res)), (vec![], 1));
+    let res = first_side(&format!("{}+d20", d20(20)), 1).await;
+    assert_eq!((crits(&res), fumbles(&res)), (vec![20.into()], 1));
+    // only kept dice count
+    let res = first_side(&format!("2{}kh1", d20(20)), 1).await;
+    assert_eq!(crits(&res), vec![RRVal::from(20)]);
+    let res = first_side(&d20(19), 1).await;
+    assert_eq!(crits(&res), vec![]);
+    let res = first_side(&d20(19), 2).await;
+    assert_eq!(crits(&res), vec![RRVal::from(19)]);
+    assert_eq!(res.breakdown(), "[**19**]");
+    // a 1 is a fumble even when the range covers everything
+    let res = first_side("d20", 100).await;
+    assert_eq!((crits(&res), fumbles(&res)), (vec![], 1));
+    let res = first_side(&d20(20), 0).await;
+    assert_eq!(crits(&res), vec![]);
+
+    // only d20s crit, and not when they're rolled for a check
+    for s in [
+        "d1",
+        "d6",
+        "8d6",
+        "4dF",
+        "d100",
+        "d[20,1]",
+        "d[3,3]",
+        "d[\"a\",\"b\"]",
+        "check(d100, 45)",
+        "check(d20, 10)",
+        "[check(d20 + 0, 10), d6]",
+    ] {
+        let res = first_side(s, 1).await;
+        assert_eq!((crits(&res), fumbles(&res)), (vec![], 0), "{s}");
+    }
+    let res = first_side("[check(d20, 10), d20]", 1).await;
+    assert_eq!(fumbles(&res), 1);
+}
+
+#[tokio::test]
+async fn eval_large_pool_test() {
+    let res = eval("1000000d6").await.unwrap();
+    assert_eq!(res.rolls[0].unlisted, 1000000);
+    assert!(res.rolls[0].dice.is_empty());
+    assert_eq!(res.breakdown(), "[1000000 dice]");
+    // the sd of the sum is about 1708, so this is over 20 sds either way
+    assert!(res.value > RRVal::from(3_465_000) && res.value < RRVal::from(3_535_000));
+
+    assert_eq!(eval("100000d1").await.unwrap().value, RRVal::from(100000));
+    assert_eq!(eval("70000d[0, 0]+1").await.unwrap().value, RRVal::from(1));
+    assert_eq!(
+        eval("100000d[5/2]").await.unwrap().value,
+        RRVal::Float(250000.)
+    );
+    assert_eq!(
+        eval("1000000000d[1, 1]").await.unwrap().value,
+        RRVal::from(1_000_000_000)
+    );
+    assert!(eval("1000000001d6").await.is_err());
+
+    let e = eval("100000d6kh3").await.unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "too many dice: 100000d6 > 65535, and only plain sums of numbers can go past that"
+    );
+    assert!(eval("100000d6!").await.is_err());
+    assert!(eval("100000d6r1").await.is_err());
+    assert!(eval("100000d10s7").await.is_err());
+    assert!(eval("100000d\"ab\"").await.is_err());
+}
+
+#[tokio::test]
+async fn eval_weighted_test() {
+    assert_eq!(eval("d{1: 1, 2: 0}").await.unwrap().value, RRVal::from(1));
+    assert_eq!(eval("3d{5: 2}").await.unwrap().value, RRVal::from(15));
+    assert_eq!(eval("0d{5: 2}").await.unwrap().value, RRVal::from(0));
+    assert_eq!(
+        eval("d{\"rare loot\": 1}").await.unwrap().value,
+        RRVal::Array("rare loot".chars().map(RRVal::Char).collect())
+    );
+    // trailing commas are fine, like in arrays
+    assert_eq!(eval("d{2: 1,}").await.unwrap().value, RRVal::from(2));
+
+    // a 2 comes up a tenth of the time, so these are 2200 +- 13.4 and 1100000 +- 300
+    let total = eval("2000d{1: 9, 2: 1}").await.unwrap().value;
+    assert!(total > RRVal::from(2100) && total < RRVal::from(2300));
+    let total = eval("1000000d{1: 9, 2: 1}").await.unwrap().value;
+    assert!(total > RRVal::from(1_095_000) && total < RRVal::from(1_105_000));
+    assert_eq!(
+        eval("100000d{1: 0, 5: 1}").await.unwrap().value,
+        RRVal::from(500000)
+    );
+}
+
+#[tokio::test]
+async fn eval_repeat_test() {
+    // each repetition is rolled separately
+    let v = eval("6x(4d6kh3)").await.unwrap();
+    assert_eq!(v.rolls.len(), 6);
+    let RRVal::Array(scores) = v.value else {
+        panic!("6x(...) should be an array");
+    };
+    assert_eq!(scores.len(), 6);
+    assert!(scores
+        .iter()
+        .all(|s| *s >= RRVal::from(3) && *s <= RRVal::from(18)));
+    let v = eval("repeat(50, d1000)").await.unwrap();
+    let RRVal::Array(rolls) = v.value else {
+        panic!("repeat(...) should be an array");
+    };
+    assert!(rolls.iter().any(|r| *r != rolls[0]));
 }
@@ -3,14 +3,22 @@ use smol_str::SmolStr;
 
 use super::value::escape_string_for_discord;
 
+#[derive(Clone)]
 pub struct Lexer<'s> {
     s: &'s str,
     prev_s: &'s str,
+    /// Whether the last token was part of an explosion like `!`, `!!` or `!p`, with nothing in
+    /// between, so a comparison straight after it is what explodes.
+    exploding: bool,
 }
 
 impl<'s> Lexer<'s> {
     pub fn new(s: &'s str) -> Self {
-        Self { s, prev_s: s }
+        Self {
+            s,
+            prev_s: s,
+            exploding: false,
+        }
     }
 
     fn peek(&self) -> char {
@@ -45,6 +53,15 @@ impl<'s> Lexer<'s> {
         self.reset();
         Some(t)
     }
+
+    /// The comparison `op`, which is a [`Token::Trigger`] if it's straight after an explosion.
+    fn cmp(&mut self, op: Op, exploding: bool) -> Option<Token<'s>> {
+        self.tok(if exploding {
+            Token::Trigger(op)
+        } else {
+            Token::Op(op)
+        })
+    }
 }
 
 impl<'s> Iterator for Lexer<'s> {
@@ -53,6 +70,7 @@ impl<'s> Iterator for Lexer<'s> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             self.reset();
+            let exploding = std::mem::take(&mut self.exploding);
             let c = self.peek();
             return match c {
                 ' ' | '\t' | '\r' | '\n' => {
@@ -73,6 +91,25 @@ impl<'s> Iterator for Lexer<'s> {
                     while matches!(self.peek(), 'a'..='z' | 'A'..='Z' | '_') {
                         self.advance();
                     }
+                    // `d%` and `dF.1` are dice literals, not an ident followed by something else.
+                    match self.so_far() {
+                        "d" => {
+                            self.eat('%');
+                        }
+                        "dF" => {
+                            let mut after = self.s.chars();
+                            if after.next() == Some('.')
+                                && matches!(after.next(), Some('1' | '2'))
+                                && !after.next().is_some_and(|c| c.is_ascii_digit())
+                            {
+                                self.advance();
+                                self.advance();
+                            }
+                        }
+                        // `!p`
+                        "p" => self.exploding = exploding,
+                        _ => (),
+                    }
                     self.tok(Token::Ident(self.so_far()))
                 }
                 '\'' => {
@@ -109,6 +146,7 @@ impl<'s> Iterator for Lexer<'s> {
                         self.advance();
                         self.tok(Token::Op(Op::BangLPar))
                     } else {
+                        self.exploding = true;
                         self.tok(Token::Op(Op::Bang))
                     }
                 }
@@ -125,7 +163,7 @@ impl<'s> Iterator for Lexer<'s> {
                     self.advance();
                     if self.peek() == '=' {
                         self.advance();
-                        self.tok(Token::Op(Op::Equal))
+                        self.cmp(Op::Equal, exploding)
                     } else {
                         self.tok(Token::Op(Op::Assign))
                     }
@@ -138,6 +176,22 @@ impl<'s> Iterator for Lexer<'s> {
                         None
                     }
                 }
+                '<' => {
+                    self.advance();
+                    if self.eat('=') {
+                        self.cmp(Op::LAngleEq, exploding)
+                    } else {
+                        self.cmp(Op::LAngle, exploding)
+                    }
+                }
+                '>' => {
+                    self.advance();
+                    if self.eat('=') {
+                        self.cmp(Op::RAngleEq, exploding)
+                    } else {
+                        self.cmp(Op::RAngle, exploding)
+                    }
+                }
                 '&' => {
                     self.advance();
                     if self.eat('&') {
@@ -174,6 +228,9 @@ const OP_MAP: phf::Map<char, Op> = phf_map! {
     '=' => Op::Assign,
     '[' => Op::LBrack,
     ']' => Op::RBrack,
+    '{' => Op::LBrace,
+    '}' => Op::RBrace,
+    ':' => Op::Colon,
     '/' => Op::Slash,
     '#' => Op::Hash,
     '<' => Op::LAngle,
@@ -215,10 +272,15 @@ declare_ops! {
     And,
     LBrack,
     RBrack,
+    LBrace,
+    RBrace,
+    Colon,
     Slash,
     Hash,
     LAngle,
     RAngle,
+    LAngleEq,
+    RAngleEq,
 }
 
 impl Op {
@@ -241,10 +303,15 @@ impl Op {
             Op::And => "&&",
             Op::LBrack => "[",
             Op::RBrack => "]",
+            Op::LBrace => "{",
+            Op::RBrace => "}",
+            Op::Colon => ":",
             Op::Slash => "/",
             Op::Hash => "#",
             Op::LAngle => "<",
             Op::RAngle => ">",
+            Op::LAngleEq => "<=",
+            Op::RAngleEq => ">=",
         }
     }
 }
@@ -253,6 +320,9 @@ impl Op {
 pub enum Token<'s> {
     Number(u64),
     Op(Op),
+    /// A comparison straight after `!`, `!!` or `!p`, like the `>` in `d6!>4`, that says which
+    /// faces explode. With a space in between, like `d6! > 4`, it's an ordinary comparison.
+    Trigger(Op),
     Ident(&'s str),
     Str(SmolStr),
     Char(char),
@@ -266,7 +336,7 @@ impl<'s> std::fmt::Display for Token<'s> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Token::Number(n) => n.fmt(f),
-            Token::Op(op) => write!(f, "{}", op.as_str()),
+            Token::Op(op) | Token::Trigger(op) => write!(f, "{}", op.as_str()),
             Token::Ident(id) => write!(f, "{}", id),
             Token::Str(s) => write!(f, "{}", escape_string_for_discord(s)),
             Token::Char(c) => write!(f, "'{}'", c.escape_default()),
@@ -303,10 +373,69 @@ pub fn lex_test() {
             Token::Number(14871),
         ]
     );
+    assert_eq!(
+        l("4dF+d%-dF.1"),
+        vec![
+            Token::Number(4),
+            Token::Ident("dF"),
+            Token::Op(Op::Plus),
+            Token::Ident("d%"),
+            Token::Op(Op::Minus),
+            Token::Ident("dF.1"),
+        ]
+    );
+    assert_eq!(
+        l("d{1:3,'a':1}"),
+        vec![
+            Token::Ident("d"),
+            Token::Op(Op::LBrace),
+            Token::Number(1),
+            Token::Op(Op::Colon),
+            Token::Number(3),
+            Token::Op(Op::Comma),
+            Token::Char('a'),
+            Token::Op(Op::Colon),
+            Token::Number(1),
+            Token::Op(Op::RBrace),
+        ]
+    );
     assert_eq!(
         l("d2!"),
         vec![Token::Ident("d"), Token::Number(2), Token::Op(Op::Bang)]
     );
+    assert_eq!(
+        l("!>1!!<=2!p==3! >4"),
+        vec![
+            Token::Op(Op::Bang),
+            Token::Trigger(Op::RAngle),
+            Token::Number(1),
+            Token::Op(Op::Bang),
+            Token::Op(Op::Bang),
+            Token::Trigger(Op::LAngleEq),
+            Token::Number(2),
+            Token::Op(Op::Bang),
+            Token::Ident("p"),
+            Token::Trigger(Op::Equal),
+            Token::Number(3),
+            Token::Op(Op::Bang),
+            Token::Op(Op::RAngle),
+            Token::Number(4),
+        ]
+    );
+    assert_eq!(
+        l("1<=2>=3<4>5"),
+        vec![
+            Token::Number(1),
+            Token::Op(Op::LAngleEq),
+            Token::Number(2),
+            Token::Op(Op::RAngleEq),
+            Token::Number(3),
+            Token::Op(Op::LAngle),
+            Token::Number(4),
+            Token::Op(Op::RAngle),
+            Token::Number(5)
+        ]
+    );
     assert_eq!(
         l("(3+4)*5"),
         vec![
@@ -1,19 +1,22 @@
+pub mod deck;
+mod dist;
 mod eval;
 mod lex;
 mod parse;
+pub mod receipt;
+mod sim;
 pub mod value;
 
+/// The seed for an evaluation that wasn't given one.
 #[cfg(not(any(test, fuzzing)))]
-fn get_rng() -> impl rand::Rng + Send {
-    use rand::{rngs::StdRng, SeedableRng};
-    StdRng::from_entropy()
+fn random_seed() -> u64 {
+    rand::random()
 }
 
 #[cfg(any(test, fuzzing))]
-pub fn get_rng() -> impl rand::Rng + Send {
+fn random_seed() -> u64 {
     // colon three
-    use rand::{rngs::StdRng, SeedableRng};
-    StdRng::seed_from_u64(0x909090)
+    0x909090
 }
 
 pub fn get_op_string_list() -> String {
@@ -39,4 +42,7 @@ fn op_string_fits_in_field() {
     assert!(get_op_string_list().len() <= 1024);
 }
 
-pub use eval::eval;
+pub use dist::{distribution, DistEvaluator, Distribution};
+pub use eval::{eval, eval_with, eval_with_rng, EvalOptions, Evaluation, OnExplosionLimit};
+pub use parse::Compare;
+pub use sim::{simulate, Simulation};
@@ -2,7 +2,79 @@ use std::iter::Peekable;
 
 use async_recursion::async_recursion;
 
-use crate::dice::lex::{Lexer, Op, Token};
+use crate::dice::{
+    dist::{DistEvaluator, Distribution, Stat},
+    eval::REPEAT_LIMIT,
+    lex::{Lexer, Op, Token},
+    value::{ffg_dice, ffg_sides, ExplodeMode, RRVal, SortOrder},
+};
+
+/// A comparison used to pick out faces of a die, e.g. the `<` in `d6r<3`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl Compare {
+    fn from_op(op: Op) -> Option<Self> {
+        Some(match op {
+            Op::Equal => Compare::Eq,
+            Op::LAngle => Compare::Lt,
+            Op::RAngle => Compare::Gt,
+            Op::LAngleEq => Compare::Le,
+            Op::RAngleEq => Compare::Ge,
+            _ => return None,
+        })
+    }
+
+    /// Whether `lhs <op> rhs`.
+    pub fn holds<T: Ord>(self, lhs: &T, rhs: &T) -> bool {
+        match self {
+            Compare::Eq => lhs == rhs,
+            Compare::Lt => lhs < rhs,
+            Compare::Gt => lhs > rhs,
+            Compare::Le => lhs <= rhs,
+            Compare::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// What a success-counting dice modifier counts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tally {
+    /// Faces that count as one success, e.g. `8d10s7`.
+    Success,
+    /// Faces that take away one success, e.g. `8d10s7f1`.
+    Failure,
+    /// Faces that count as two successes, e.g. `8d10s7dbl10`.
+    Double,
+}
+
+impl Tally {
+    /// How a bare target number is compared against each face.
+    pub fn bare(self) -> Option<Compare> {
+        // A bare target number means "at least" for successes and "at most" for failures, like in
+        // most dice pool systems.
+        match self {
+            Tally::Success => Some(Compare::Ge),
+            Tally::Failure => Some(Compare::Le),
+            Tally::Double => None,
+        }
+    }
+}
+
+/// Which faces a dice modifier like reroll applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition<V> {
+    /// A face, or an array of faces, e.g. `d6r1` or `d6r(1,2)`.
+    Faces(V),
+    /// All faces that compare a certain way to a value, e.g. `d6r<3`.
+    Compare(Compare, V),
+}
 
 #[trait_variant::make(ParseIns: Send)]
 #[allow(dead_code)]
@@ -16,13 +88,25 @@ pub trait UnusedParseIns {
         right: Self::Value,
         c: Op,
     ) -> anyhow::Result<Self::Value>;
-    async fn pfxop(&self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value>;
-    async fn sfxop(&self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value>;
+    async fn pfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value>;
+    async fn sfxop(&mut self, inner: Self::Value, c: Op) -> anyhow::Result<Self::Value>;
+    async fn explode_suffix(
+        &mut self,
+        inner: Self::Value,
+        mode: ExplodeMode,
+        cond: Option<Condition<Self::Value>>,
+    ) -> anyhow::Result<Self::Value>;
     async fn dice(
         &mut self,
         num: Option<Self::Value>,
         sides: Self::Value,
     ) -> anyhow::Result<Self::Value>;
+    /// Dice whose sides aren't all equally likely, from `d{face: weight, ...}`.
+    async fn weighted_dice(
+        &mut self,
+        num: Option<Self::Value>,
+        sides: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value>;
     async fn keep_highest(
         &mut self,
         dice: Self::Value,
@@ -33,17 +117,84 @@ pub trait UnusedParseIns {
         dice: Self::Value,
         keep: Self::Value,
     ) -> anyhow::Result<Self::Value>;
+    async fn drop_highest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value>;
+    async fn drop_lowest(
+        &mut self,
+        dice: Self::Value,
+        drop: Self::Value,
+    ) -> anyhow::Result<Self::Value>;
     async fn explode(
         &mut self,
         dice: Self::Value,
         keep: Self::Value,
     ) -> anyhow::Result<Self::Value>;
+    async fn reroll(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        once: bool,
+    ) -> anyhow::Result<Self::Value>;
+    async fn tally(
+        &mut self,
+        dice: Self::Value,
+        cond: Condition<Self::Value>,
+        tally: Tally,
+    ) -> anyhow::Result<Self::Value>;
+    /// `u`: dice that land on a face another die in the pool already shows are rerolled.
+    async fn unique(&mut self, dice: Self::Value) -> anyhow::Result<Self::Value>;
+    /// `s`, `sa` or `sd`, on a dice pool or an array.
+    async fn sort(&mut self, dice: Self::Value, order: SortOrder) -> anyhow::Result<Self::Value>;
+    /// `faces(pool)`: each kept die of a pool, rather than their sum.
+    async fn faces(&mut self, dice: Self::Value) -> anyhow::Result<Self::Value>;
+    /// `max(a, b, ...)`, or `min(a, b, ...)` if not `highest`, with each pool rolled on its own.
+    async fn best(&mut self, pools: Vec<Self::Value>, highest: bool)
+        -> anyhow::Result<Self::Value>;
+    /// The degree of success of `check(roll, skill)`. `rolls_before` is what
+    /// [`UnusedParseIns::rolls_made`] was before its arguments, so the dice they rolled are
+    /// known.
+    async fn check(
+        &mut self,
+        roll: Self::Value,
+        skill: Self::Value,
+        rolls_before: usize,
+    ) -> anyhow::Result<Self::Value>;
+    /// How many pools of dice have been rolled so far.
+    fn rolls_made(&self) -> usize;
+    /// Checks the number of repetitions in `6x(...)` or `repeat(6, ...)`.
+    async fn repeat_count(&mut self, count: Self::Value) -> anyhow::Result<u32>;
+    /// A bag of symbols, from `{symbol: count, ...}`.
+    async fn bag(
+        &mut self,
+        entries: Vec<(Self::Value, Self::Value)>,
+    ) -> anyhow::Result<Self::Value>;
+    /// `cancel(bag, a, b)`: the symbols `a` and `b` cancel each other out in `bag`.
+    async fn cancel(
+        &mut self,
+        bag: Self::Value,
+        a: Self::Value,
+        b: Self::Value,
+    ) -> anyhow::Result<Self::Value>;
+    /// A value that's known without evaluating anything, like the sides of `dAbility`.
+    async fn value(&mut self, v: RRVal) -> anyhow::Result<Self::Value>;
+    /// `draw(n)`: the names of the next `n` cards of the deck.
+    async fn draw(&mut self, count: Self::Value) -> anyhow::Result<Self::Value>;
+    /// A statistic of a sub-expression's distribution, e.g. `P(2d6 >= 8)`.
+    async fn stat(&mut self, stat: Stat, dist: Distribution) -> anyhow::Result<Self::Value>;
+    /// What works out the distributions for [`UnusedParseIns::stat`].
+    fn dist_evaluator(&self) -> DistEvaluator;
     async fn mk_array(&mut self, arr: Vec<Self::Value>) -> anyhow::Result<Self::Value>;
 }
 
 pub struct Parser<'s, I: ParseIns> {
     lex: Peekable<Lexer<'s>>,
     ins: I,
+    /// How many times expressions have been repeated so far, counting every repetition of nested
+    /// ones, so `10x(10x(d6))` is 110.
+    repetitions: u32,
 }
 
 // stupid
@@ -60,6 +211,8 @@ fn infix_prec(op: Op) -> Option<(u8, u8)> {
         Op::Equal => (9, 10),
         Op::LAngle => (9, 10),
         Op::RAngle => (9, 10),
+        Op::LAngleEq => (9, 10),
+        Op::RAngleEq => (9, 10),
         Op::Comma => (7, 8),
         Op::Or => (5, 6),
         Op::Assign => (4, 3),
@@ -68,6 +221,19 @@ fn infix_prec(op: Op) -> Option<(u8, u8)> {
     })
 }
 
+/// Whether `name` followed by `(` is a call to a built-in function rather than a variable.
+fn is_function(name: &str) -> bool {
+    matches!(
+        name,
+        "repeat" | "check" | "faces" | "max" | "min" | "wild" | "cancel" | "draw"
+    ) || Stat::from_name(name).is_some()
+}
+
+/// Whether `id` is a die with fixed sides, like `d%`, `dF` or the narrative dice like `dAbility`.
+fn is_fixed_die(id: &str) -> bool {
+    matches!(id, "d%" | "dF" | "dF.1" | "dF.2") || ffg_dice().any(|d| d == id)
+}
+
 fn prefix_prec(op: Op) -> Option<u8> {
     Some(match op {
         Op::Plus => 40,
@@ -91,6 +257,7 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
         Self {
             lex: lex.peekable(),
             ins: i,
+            repetitions: 0,
         }
     }
 
@@ -151,6 +318,7 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
                     self.ins.mk_array(arr).await?
                 }
             }
+            Token::Op(Op::LBrace) => self.bag().await?,
             Token::Op(op) => {
                 // TODO treat BangLPar and RParBang as logical not of (expr)
                 self.advance();
@@ -167,9 +335,20 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
             }
             Token::Ident("d") => {
                 self.advance();
-                let p = 60;
-                let inner = self.expr(p).await?;
-                self.ins.dice(None, inner).await?
+                self.dice(None, 60).await?
+            }
+            Token::Ident(id) if is_fixed_die(id) => {
+                self.advance();
+                let sides = self.fixed_sides(id).await?;
+                self.ins.dice(None, sides).await?
+            }
+            Token::Ident(name) if is_function(name) => {
+                self.advance();
+                if self.eat(&Token::Op(Op::LPar)) {
+                    self.call(name).await?
+                } else {
+                    self.ins.literal(t).await?
+                }
             }
             _ => self.literal().await?,
         };
@@ -177,7 +356,26 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
             let t = self.peek().clone();
             match t {
                 Token::Op(op) => {
-                    if let Some(p) = suffix_prec(op) {
+                    if op == Op::Bang {
+                        let p = suffix_prec(op).unwrap();
+                        if min_prec <= p {
+                            self.advance();
+                            let mode = if self.eat(&Token::Op(Op::Bang)) {
+                                ExplodeMode::Compound
+                            } else if self.eat(&Token::Ident("p")) {
+                                ExplodeMode::Penetrate
+                            } else {
+                                ExplodeMode::Standard
+                            };
+                            // `d6!>4` explodes on 5s and 6s, but `d6! > 4` compares the roll.
+                            let cond = match *self.peek() {
+                                Token::Trigger(_) => Some(self.condition(56).await?),
+                                _ => None,
+                            };
+                            first = self.ins.explode_suffix(first, mode, cond).await?;
+                            continue;
+                        }
+                    } else if let Some(p) = suffix_prec(op) {
                         if min_prec <= p {
                             self.advance();
                             first = self.ins.sfxop(first, op).await?;
@@ -209,8 +407,25 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
                     let (lp, rp) = (59, 60);
                     if min_prec <= lp {
                         self.advance();
-                        let rhs = self.expr(rp).await?;
-                        first = self.ins.dice(Some(first), rhs).await?;
+                        first = self.dice(Some(first), rp).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident(id) if is_fixed_die(id) => {
+                    if min_prec <= 59 {
+                        self.advance();
+                        let sides = self.fixed_sides(id).await?;
+                        first = self.ins.dice(Some(first), sides).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident("x") => {
+                    let (lp, rp) = (49, 50);
+                    if min_prec <= lp {
+                        self.advance();
+                        first = self.repeat(first, rp).await?;
                         continue;
                     }
                     return Ok(first);
@@ -235,6 +450,65 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
                     }
                     return Ok(first);
                 }
+                Token::Ident("DH" | "dh" | "Dh" | "dH") => {
+                    let (lp, rp) = (55, 56);
+                    if min_prec <= lp {
+                        self.advance();
+                        let rhs = self.expr(rp).await?;
+                        first = self.ins.drop_highest(first, rhs).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident("DL" | "dl" | "Dl" | "dL") => {
+                    let (lp, rp) = (55, 56);
+                    if min_prec <= lp {
+                        self.advance();
+                        let rhs = self.expr(rp).await?;
+                        first = self.ins.drop_lowest(first, rhs).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident(id @ ("r" | "R" | "ro" | "RO" | "Ro" | "rO")) => {
+                    let (lp, rp) = (55, 56);
+                    if min_prec <= lp {
+                        self.advance();
+                        let cond = self.condition(rp).await?;
+                        let once = id.len() == 2;
+                        first = self.ins.reroll(first, cond, once).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident(id @ ("u" | "U" | "sa" | "SA" | "sd" | "SD")) => {
+                    if min_prec <= 55 {
+                        self.advance();
+                        first = self.pool_modifier(first, id).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
+                Token::Ident(id @ ("s" | "S" | "f" | "F" | "dbl" | "DBL")) => {
+                    let (lp, rp) = (55, 56);
+                    if min_prec <= lp {
+                        self.advance();
+                        // a bare `s` sorts, while `s6` or `s>4` counts successes
+                        if matches!(id, "s" | "S") && !self.starts_condition() {
+                            first = self.pool_modifier(first, id).await?;
+                            continue;
+                        }
+                        let cond = self.condition(rp).await?;
+                        let tally = match id {
+                            "s" | "S" => Tally::Success,
+                            "f" | "F" => Tally::Failure,
+                            _ => Tally::Double,
+                        };
+                        first = self.ins.tally(first, cond, tally).await?;
+                        continue;
+                    }
+                    return Ok(first);
+                }
                 Token::Eof => return Ok(first),
                 bad => {
                     anyhow::bail!("unexpected token `{}`", bad);
@@ -243,6 +517,206 @@ impl<'s, I: ParseIns + Send> Parser<'s, I> {
         }
     }
 
+    /// Applies one of the dice modifiers that don't take any faces, `u` or `s`/`sa`/`sd`.
+    #[async_recursion]
+    async fn pool_modifier(&mut self, dice: I::Value, id: &'s str) -> pres!() {
+        match id {
+            "u" | "U" => self.ins.unique(dice).await,
+            "sd" | "SD" => self.ins.sort(dice, SortOrder::Descending).await,
+            _ => self.ins.sort(dice, SortOrder::Ascending).await,
+        }
+    }
+
+    /// Whether the next token could start the faces a dice modifier applies to.
+    fn starts_condition(&mut self) -> bool {
+        match *self.peek() {
+            Token::Op(op) => {
+                Compare::from_op(op).is_some() || matches!(op, Op::LPar | Op::LBrack | Op::Hash)
+            }
+            Token::Eof => false,
+            _ => true,
+        }
+    }
+
+    /// Parses the faces a dice modifier applies to, either as a comparison (`<3`) or as a plain
+    /// expression giving the faces themselves (`(1,2)`).
+    async fn condition(&mut self, prec: u8) -> anyhow::Result<Condition<I::Value>> {
+        if let Token::Op(op) | Token::Trigger(op) = *self.peek() {
+            if let Some(cmp) = Compare::from_op(op) {
+                self.advance();
+                let v = self.expr(prec).await?;
+                return Ok(Condition::Compare(cmp, v));
+            }
+        }
+        Ok(Condition::Faces(self.expr(prec).await?))
+    }
+
+    /// Parses the arguments and closing parenthesis of a call to the built-in function `name`.
+    #[async_recursion]
+    async fn call(&mut self, name: &'s str) -> pres!() {
+        let (_, comma_rp) = infix_prec(Op::Comma).unwrap();
+        let res = match name {
+            "repeat" => {
+                let count = self.expr(comma_rp).await?;
+                self.expect(&Token::Op(Op::Comma))?;
+                self.repeat(count, 0).await?
+            }
+            "faces" => {
+                let dice = self.expr(0).await?;
+                self.ins.faces(dice).await?
+            }
+            "max" | "min" => {
+                let mut pools = vec![self.expr(comma_rp).await?];
+                while self.eat(&Token::Op(Op::Comma)) {
+                    pools.push(self.expr(comma_rp).await?);
+                }
+                self.ins.best(pools, name == "max").await?
+            }
+            "wild" => {
+                // Savage Worlds: an exploding trait die and an exploding d6, whichever's higher
+                let trait_die = self.expr(0).await?;
+                let six = self.ins.literal(Token::Number(6)).await?;
+                let wild_die = self.ins.dice(None, six).await?;
+                let mut pools = vec![];
+                for die in [trait_die, wild_die] {
+                    let mode = ExplodeMode::Standard;
+                    pools.push(self.ins.explode_suffix(die, mode, None).await?);
+                }
+                self.ins.best(pools, true).await?
+            }
+            "cancel" => {
+                let bag = self.expr(comma_rp).await?;
+                self.expect(&Token::Op(Op::Comma))?;
+                let a = self.expr(comma_rp).await?;
+                self.expect(&Token::Op(Op::Comma))?;
+                let b = self.expr(comma_rp).await?;
+                self.ins.cancel(bag, a, b).await?
+            }
+            "draw" => {
+                // `draw()` is the same as `draw(1)`
+                let count = if *self.peek() == Token::Op(Op::RPar) {
+                    self.ins.literal(Token::Number(1)).await?
+                } else {
+                    self.expr(0).await?
+                };
+                self.ins.draw(count).await?
+            }
+            "check" => {
+                let rolls_before = self.ins.rolls_made();
+                let roll = self.expr(comma_rp).await?;
+                self.expect(&Token::Op(Op::Comma))?;
+                let skill = self.expr(comma_rp).await?;
+                self.ins.check(roll, skill, rolls_before).await?
+            }
+            _ => {
+                let dist = self.distribution().await?;
+                let stat = Stat::from_name(name).unwrap();
+                self.ins.stat(stat, dist).await?
+            }
+        };
+        self.expect(&Token::Op(Op::RPar))?;
+        Ok(res)
+    }
+
+    /// Parses the next expression binding at least as tightly as `prec` over and over, `count`
+    /// times, so that each repetition is rolled separately, and puts the results in an array.
+    #[async_recursion]
+    async fn repeat(&mut self, count: I::Value, prec: u8) -> pres!() {
+        let n = self.ins.repeat_count(count).await?;
+        self.repetitions += n;
+        if self.repetitions > REPEAT_LIMIT {
+            anyhow::bail!("too many repetitions: more than {REPEAT_LIMIT} in total".to_string());
+        }
+        let start = self.lex.clone();
+        let mut results = Vec::new();
+        for i in 0..n {
+            crate::util::yield_point().await;
+            if i > 0 {
+                self.lex = start.clone();
+            }
+            results.push(self.expr(prec).await?);
+        }
+        self.ins.mk_array(results).await
+    }
+
+    /// Parses the sides of `num` dice after a `d`, which are either weighted like
+    /// `{"common": 7, "rare": 1}` or an expression binding at least as tightly as `prec`.
+    #[async_recursion]
+    async fn dice(&mut self, num: Option<I::Value>, prec: u8) -> pres!() {
+        if *self.peek() == Token::Op(Op::LBrace) {
+            let sides = self.weighted_sides().await?;
+            self.ins.weighted_dice(num, sides).await
+        } else {
+            let sides = self.expr(prec).await?;
+            self.ins.dice(num, sides).await
+        }
+    }
+
+    /// Parses a bag of symbols like `{"success": 2, "advantage": 1}`.
+    #[async_recursion]
+    async fn bag(&mut self) -> pres!() {
+        let entries = self.weighted_sides().await?;
+        self.ins.bag(entries).await
+    }
+
+    /// Parses weighted sides like `{"common": 7, "rare": 1}`, as a list of each face and its
+    /// weight.
+    async fn weighted_sides(&mut self) -> anyhow::Result<Vec<(I::Value, I::Value)>> {
+        self.expect(&Token::Op(Op::LBrace))?;
+        let (_, comma_rp) = infix_prec(Op::Comma).unwrap();
+        let mut sides = Vec::new();
+        while !self.eat(&Token::Op(Op::RBrace)) {
+            let face = self.expr(comma_rp).await?;
+            self.expect(&Token::Op(Op::Colon))?;
+            let weight = self.expr(comma_rp).await?;
+            sides.push((face, weight));
+            if self.eat(&Token::Op(Op::RBrace)) {
+                break;
+            }
+            self.expect(&Token::Op(Op::Comma))?;
+        }
+        Ok(sides)
+    }
+
+    /// Parses the next expression with a [`DistEvaluator`] instead, working out its distribution
+    /// without rolling anything.
+    async fn distribution(&mut self) -> anyhow::Result<Distribution> {
+        let lex = std::mem::replace(&mut self.lex, Lexer::new("").peekable());
+        let mut sub = Parser {
+            lex,
+            ins: self.ins.dist_evaluator(),
+            repetitions: self.repetitions,
+        };
+        let res = sub.expr(0).await;
+        self.lex = sub.lex;
+        self.repetitions = sub.repetitions;
+        sub.ins.resolve(res?).await
+    }
+
+    /// The sides of the dice literals `d%` (a d100) and `dF` (Fate dice).
+    /// `dF.1` is the Fudge variant with a single `-1` and a single `+1`; `dF.2` is the same as `dF`.
+    /// The narrative dice like `dAbility` have bags of symbols for sides.
+    async fn fixed_sides(&mut self, id: &str) -> pres!() {
+        if let Some(sides) = ffg_sides(id) {
+            return self.ins.value(RRVal::Array(sides)).await;
+        }
+        let faces: &[i64] = match id {
+            "d%" => return self.ins.literal(Token::Number(100)).await,
+            "dF.1" => &[-1, 0, 0, 0, 0, 1],
+            _ => &[-1, -1, 0, 0, 1, 1],
+        };
+        let mut sides = Vec::new();
+        for &face in faces {
+            let v = self.ins.literal(Token::Number(face.unsigned_abs())).await?;
+            sides.push(if face < 0 {
+                self.ins.pfxop(v, Op::Minus).await?
+            } else {
+                v
+            });
+        }
+        self.ins.mk_array(sides).await
+    }
+
     async fn literal(&mut self) -> pres!() {
         let t = self.peek().clone();
         match self.ins.literal(t).await {
@@ -0,0 +1,120 @@
+//! Commit–reveal for contested rolls: a session's secret is hashed and published up front, every
+//! roll in the session is seeded from the secret and the roll's index, and revealing the secret
+//! at the end lets anyone check both the hash and every roll.
+
+use sha2::{Digest, Sha256};
+
+/// A run of rolls whose seeds all come from one secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    secret: [u8; 32],
+    rolls: u64,
+}
+
+impl Session {
+    /// Starts a session with a fresh random secret.
+    pub fn new() -> Self {
+        Self::from_secret(rand::random())
+    }
+
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        Self { secret, rolls: 0 }
+    }
+
+    /// The hash of the secret, to be published before any rolls are made.
+    pub fn commitment(&self) -> String {
+        to_hex(&Sha256::digest(self.secret))
+    }
+
+    /// The secret itself, to be revealed once the session is over.
+    pub fn secret(&self) -> String {
+        to_hex(&self.secret)
+    }
+
+    /// How many rolls have been made in this session.
+    pub fn rolls(&self) -> u64 {
+        self.rolls
+    }
+
+    /// The index and seed of the next roll in the session.
+    pub fn next_roll(&mut self) -> (u64, u64) {
+        let index = self.rolls;
+        self.rolls += 1;
+        (index, roll_seed(&self.secret, index))
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The seed for roll number `index` of the session with `secret`, for
+/// [`EvalOptions::seed`](super::EvalOptions::seed).
+pub fn roll_seed(secret: &[u8; 32], index: u64) -> u64 {
+    let hash = Sha256::new()
+        .chain_update(secret)
+        .chain_update(index.to_le_bytes())
+        .finalize();
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+/// Parses a secret as given by [`Session::secret`].
+pub fn parse_secret(hex: &str) -> anyhow::Result<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        anyhow::bail!("a session secret is 64 hex digits".to_string());
+    }
+    let mut secret = [0; 32];
+    for (i, byte) in secret.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow::anyhow!("a session secret is 64 hex digits"))?;
+    }
+    Ok(secret)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[tokio::test]
+async fn receipt_test() {
+    use super::{eval_with, EvalOptions};
+
+    let mut session = Session::from_secret([0; 32]);
+    assert_eq!(
+        session.commitment(),
+        "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+    );
+    assert_eq!(session.secret(), "0".repeat(64));
+    assert_eq!(parse_secret(&session.secret()).unwrap(), [0; 32]);
+
+    let (first, seed) = session.next_roll();
+    assert_eq!(first, 0);
+    assert_eq!(seed, roll_seed(&[0; 32], 0));
+    let (second, other) = session.next_roll();
+    assert_eq!(second, 1);
+    assert_ne!(seed, other);
+    assert_eq!(session.rolls(), 2);
+
+    // whoever has the secret can redo the rolls
+    let secret = parse_secret(&session.secret()).unwrap();
+    let options = |seed| EvalOptions {
+        seed: Some(seed),
+        ..Default::default()
+    };
+    let roll = eval_with("10d20", options(other)).await.unwrap();
+    let redone = eval_with("10d20", options(roll_seed(&secret, 1)))
+        .await
+        .unwrap();
+    assert_eq!(roll.rolls, redone.rolls);
+
+    let mut session = Session::new();
+    assert_eq!(
+        Session::from_secret(parse_secret(&session.secret()).unwrap()).next_roll(),
+        session.next_roll()
+    );
+    assert!(parse_secret("abc").is_err());
+    assert!(parse_secret(&"g".repeat(64)).is_err());
+}
@@ -0,0 +1,135 @@
+//! Monte Carlo simulation, for expressions that [`distribution`](super::distribution) can't
+//! work out exactly.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::{eval, value::RRVal};
+
+/// The results of rolling an expression lots of times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    /// How many times each outcome came up.
+    pub frequencies: BTreeMap<RRVal, usize>,
+    /// How many times the expression was rolled.
+    pub runs: usize,
+    /// Whether the time limit ran out before all the requested runs were done.
+    pub timed_out: bool,
+}
+
+impl Simulation {
+    pub fn min(&self) -> Option<&RRVal> {
+        self.frequencies.keys().next()
+    }
+
+    pub fn max(&self) -> Option<&RRVal> {
+        self.frequencies.keys().next_back()
+    }
+
+    /// Every outcome as a number, along with how many times it came up, or `None` if any of the
+    /// outcomes aren't numbers.
+    fn numeric(&self) -> Option<Vec<(f64, usize)>> {
+        self.frequencies
+            .iter()
+            .map(|(v, n)| match v {
+                RRVal::Int(i) => Some((i.to_f64(), *n)),
+                RRVal::Float(f) => Some((*f, *n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.runs == 0 {
+            return None;
+        }
+        let sum: f64 = self.numeric()?.iter().map(|(v, n)| v * *n as f64).sum();
+        Some(sum / self.runs as f64)
+    }
+
+    /// The population standard deviation.
+    pub fn stddev(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        let sum: f64 = self
+            .numeric()?
+            .iter()
+            .map(|(v, n)| (v - mean).powi(2) * *n as f64)
+            .sum();
+        Some((sum / self.runs as f64).sqrt())
+    }
+
+    /// The lowest outcome that at least `percent`% of runs were at or below.
+    pub fn percentile(&self, percent: f64) -> Option<&RRVal> {
+        let rank = ((percent / 100. * self.runs as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        for (v, n) in &self.frequencies {
+            seen += n;
+            if seen >= rank {
+                return Some(v);
+            }
+        }
+        None
+    }
+}
+
+/// Rolls `expr` `runs` times, each with a fresh evaluator, or until `time_limit` runs out.
+pub async fn simulate(expr: &str, runs: usize, time_limit: Duration) -> anyhow::Result<Simulation> {
+    let start = Instant::now();
+    let mut sim = Simulation {
+        frequencies: BTreeMap::new(),
+        runs: 0,
+        timed_out: false,
+    };
+    while sim.runs < runs {
+        if start.elapsed() > time_limit {
+            sim.timed_out = true;
+            break;
+        }
+        let value = eval(expr).await?.value;
+        *sim.frequencies.entry(value).or_insert(0) += 1;
+        sim.runs += 1;
+        crate::util::yield_point().await;
+    }
+    Ok(sim)
+}
+
+#[tokio::test]
+async fn simulate_test() {
+    let sim = simulate("3d1+1", 50, Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(sim.runs, 50);
+    assert!(!sim.timed_out);
+    assert_eq!(sim.frequencies, BTreeMap::from([(RRVal::from(4), 50)]));
+    assert_eq!(sim.mean(), Some(4.));
+    assert_eq!(sim.stddev(), Some(0.));
+    assert_eq!(sim.percentile(50.), Some(&RRVal::from(4)));
+
+    let sim = Simulation {
+        frequencies: BTreeMap::from([(1.into(), 1), (2.into(), 2), (6.into(), 1)]),
+        runs: 4,
+        timed_out: false,
+    };
+    assert_eq!(sim.min(), Some(&RRVal::from(1)));
+    assert_eq!(sim.max(), Some(&RRVal::from(6)));
+    assert_eq!(sim.mean(), Some(11. / 4.));
+    assert_eq!(sim.stddev(), Some((14.75f64 / 4.).sqrt()));
+    assert_eq!(sim.percentile(0.), Some(&RRVal::from(1)));
+    assert_eq!(sim.percentile(25.), Some(&RRVal::from(1)));
+    assert_eq!(sim.percentile(50.), Some(&RRVal::from(2)));
+    assert_eq!(sim.percentile(75.), Some(&RRVal::from(2)));
+    assert_eq!(sim.percentile(100.), Some(&RRVal::from(6)));
+
+    let sim = simulate("\"ab\"", 3, Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(sim.mean(), None);
+    assert!(sim.percentile(50.).is_some());
+
+    let sim = simulate("d6", 1_000_000, Duration::ZERO).await.unwrap();
+    assert!(sim.timed_out);
+    assert!(sim.runs < 1_000_000);
+    assert!(simulate("x", 10, Duration::from_secs(10)).await.is_err());
+}
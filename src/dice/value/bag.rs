@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use smol_str::SmolStr;
+
+use super::RRVal;
+
+/// A bag of symbols, for dice like those of Genesys whose faces show symbols rather than numbers.
+/// Adding bags together pours them into one, and then any symbols that cancel each other out
+/// are taken out in pairs. Bags are compared by their symbols alone.
+#[derive(Debug, Clone, Default)]
+pub struct Bag {
+    /// How many of each symbol are in the bag, leaving out any there are none of.
+    symbols: BTreeMap<SmolStr, u32>,
+    /// Pairs of symbols that cancel each other out, one for one.
+    cancels: BTreeSet<(SmolStr, SmolStr)>,
+}
+
+impl Bag {
+    pub fn new(symbols: impl IntoIterator<Item = (SmolStr, u32)>) -> Self {
+        let mut bag = Self::default();
+        for (symbol, n) in symbols {
+            bag.put(symbol, n);
+        }
+        bag
+    }
+
+    /// How many of `symbol` are in the bag.
+    pub fn count(&self, symbol: &str) -> u32 {
+        self.symbols.get(symbol).copied().unwrap_or(0)
+    }
+
+    /// Every symbol in the bag, with how many of it there are.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols.iter().map(|(s, n)| (s.as_str(), *n))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Makes `a` and `b` cancel each other out in this bag, and in any bag it's added to.
+    pub fn cancelling(mut self, a: SmolStr, b: SmolStr) -> Self {
+        if a != b {
+            self.cancels.insert(if a < b { (a, b) } else { (b, a) });
+            self.cancel();
+        }
+        self
+    }
+
+    /// Both bags poured into one.
+    pub fn union(mut self, other: Bag) -> Self {
+        for (symbol, n) in other.symbols {
+            self.put(symbol, n);
+        }
+        self.cancels.extend(other.cancels);
+        self.cancel();
+        self
+    }
+
+    /// This bag with everything in `other` taken out of it, as far as it goes.
+    pub fn difference(mut self, other: &Bag) -> Self {
+        for (symbol, n) in &other.symbols {
+            if let Some(m) = self.symbols.get_mut(symbol) {
+                *m = m.saturating_sub(*n);
+            }
+        }
+        self.symbols.retain(|_, n| *n > 0);
+        self
+    }
+
+    /// `n` of this bag poured into one.
+    pub fn times(mut self, n: u32) -> Self {
+        for m in self.symbols.values_mut() {
+            *m = m.saturating_mul(n);
+        }
+        self.symbols.retain(|_, n| *n > 0);
+        self
+    }
+
+    fn put(&mut self, symbol: SmolStr, n: u32) {
+        if n > 0 {
+            let m = self.symbols.entry(symbol).or_default();
+            *m = m.saturating_add(n);
+        }
+    }
+
+    fn cancel(&mut self) {
+        for (a, b) in &self.cancels {
+            let n = self.count(a).min(self.count(b));
+            for s in [a, b] {
+                if let Some(m) = self.symbols.get_mut(s) {
+                    *m -= n;
+                }
+            }
+        }
+        self.symbols.retain(|_, n| *n > 0);
+    }
+}
+
+impl PartialEq for Bag {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbols == other.symbols
+    }
+}
+
+impl Eq for Bag {}
+
+impl PartialOrd for Bag {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Bag {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.symbols.cmp(&other.symbols)
+    }
+}
+
+impl std::fmt::Display for Bag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // in code formatting, like strings, so that symbols can't be used for mentions
+        f.write_str("``{")?;
+        for (i, (symbol, n)) in self.symbols.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "\"{}\": {n}", symbol.replace('`', "`\u{200b}"))?;
+        }
+        f.write_str("}``")
+    }
+}
+
+/// The symbols on Genesys and Star Wars dice, by the letter they're written with in [`FFG_DICE`].
+const FFG_SYMBOLS: &[(char, &[&str])] = &[
+    ('s', &["success"]),
+    ('f', &["failure"]),
+    ('a', &["advantage"]),
+    ('t', &["threat"]),
+    // a triumph and a despair count as a success and a failure as well
+    ('T', &["triumph", "success"]),
+    ('D', &["despair", "failure"]),
+    ('l', &["light"]),
+    ('d', &["dark"]),
+];
+
+/// The faces of the Genesys and Star Wars narrative dice, one symbol per letter.
+const FFG_DICE: &[(&str, &[&str])] = &[
+    ("dBoost", &["", "", "s", "sa", "aa", "a"]),
+    ("dSetback", &["", "", "f", "f", "t", "t"]),
+    ("dAbility", &["", "s", "s", "ss", "a", "a", "sa", "aa"]),
+    ("dDifficulty", &["", "f", "ff", "t", "t", "t", "tt", "ft"]),
+    (
+        "dProficiency",
+        &[
+            "", "s", "s", "ss", "ss", "a", "sa", "sa", "sa", "aa", "aa", "T",
+        ],
+    ),
+    (
+        "dChallenge",
+        &[
+            "", "f", "f", "ff", "ff", "t", "t", "ft", "ft", "tt", "tt", "D",
+        ],
+    ),
+    (
+        "dForce",
+        &[
+            "d", "d", "d", "d", "d", "d", "dd", "l", "l", "ll", "ll", "ll",
+        ],
+    ),
+];
+
+/// The names of the Genesys and Star Wars narrative dice, like `dBoost`.
+pub fn ffg_dice() -> impl Iterator<Item = &'static str> {
+    FFG_DICE.iter().map(|(name, _)| *name)
+}
+
+/// The sides of the Genesys and Star Wars narrative die called `name`, like `dAbility`, whose
+/// successes cancel out failures and advantages cancel out threats.
+pub fn ffg_sides(name: &str) -> Option<Vec<RRVal>> {
+    let (_, faces) = FFG_DICE.iter().find(|(n, _)| *n == name)?;
+    let sides = faces.iter().map(|face| {
+        let symbols = face.chars().flat_map(|c| {
+            let (_, symbols) = FFG_SYMBOLS.iter().find(|(l, _)| *l == c).unwrap();
+            symbols.iter().map(|s| (SmolStr::new(s), 1))
+        });
+        RRVal::Bag(
+            Bag::new(symbols)
+                .cancelling("success".into(), "failure".into())
+                .cancelling("advantage".into(), "threat".into()),
+        )
+    });
+    Some(sides.collect())
+}
+
+#[test]
+fn bag_test() {
+    let bag =
+        |symbols: &[(&str, u32)]| Bag::new(symbols.iter().map(|(s, n)| (SmolStr::new(s), *n)));
+
+    let a = bag(&[("success", 2), ("advantage", 1)]);
+    let b = bag(&[("failure", 1), ("threat", 3), ("advantage", 0)]);
+    let both = a.clone().union(b.clone());
+    assert_eq!(both.count("success"), 2);
+    assert_eq!(both.count("threat"), 3);
+    assert_eq!(both.count("advantage"), 1);
+
+    // cancelling carries over to whatever the bag's added to
+    let a = a.cancelling("success".into(), "failure".into());
+    let both = b.clone().union(a.clone());
+    assert_eq!(
+        both,
+        bag(&[("success", 1), ("advantage", 1), ("threat", 3)])
+            .cancelling("failure".into(), "success".into())
+    );
+    let both = both.cancelling("threat".into(), "advantage".into());
+    assert_eq!(
+        both.symbols().collect::<Vec<_>>(),
+        [("success", 1), ("threat", 2)]
+    );
+
+    assert_eq!(a.clone().times(3).count("success"), 6);
+    assert!(a.clone().times(0).is_empty());
+    assert_eq!(
+        a.difference(&bag(&[("success", 5)]))
+            .symbols()
+            .collect::<Vec<_>>(),
+        [("advantage", 1)]
+    );
+
+    assert_eq!(
+        format!("{}", bag(&[("b`", 1), ("a", 2)])),
+        "``{\"a\": 2, \"b`\u{200b}\": 1}``"
+    );
+    assert_eq!(format!("{}", Bag::default()), "``{}``");
+
+    for name in ffg_dice() {
+        let sides = ffg_sides(name).unwrap();
+        assert!([6, 8, 12].contains(&sides.len()), "{name}");
+    }
+    let proficiency = ffg_sides("dProficiency").unwrap();
+    let RRVal::Bag(triumph) = &proficiency[11] else {
+        panic!("narrative dice have bags for sides");
+    };
+    assert_eq!(
+        triumph.symbols().collect::<Vec<_>>(),
+        [("success", 1), ("triumph", 1)]
+    );
+    assert_eq!(ffg_sides("d6"), None);
+}
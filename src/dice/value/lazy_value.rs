@@ -1,8 +1,11 @@
 use rug::Integer;
 
-use crate::dice::eval::Evaluator;
+use crate::dice::{
+    eval::Evaluator,
+    parse::{Compare, Condition, Tally},
+};
 
-use super::{resolve_dice, Place, RRVal, RVal, ResolveError};
+use super::{Bag, Place, RRVal, RVal, ResolveError};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum LazyValue {
@@ -13,49 +16,248 @@ pub enum LazyValue {
     /// A place (aka lvalue) is a reference to some variable (and possibly array indexes in to that
     /// variable).
     Place(Place),
-    LazyDice {
-        num: u32,
-        sides: Vec<RRVal>,
-        lowest_idx: u32,
-        highest_idx: u32,
-        explode: Vec<RRVal>,
-    },
+    LazyDice(LazyDice),
+    Bag(Bag),
+}
+
+/// Dice that haven't been rolled yet, along with all the modifiers applied to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyDice {
+    pub num: u32,
+    pub sides: Vec<RRVal>,
+    /// How likely each side is to come up, relative to the others, if they aren't all equally
+    /// likely.
+    pub weights: Option<Vec<u32>>,
+    /// Index of the lowest kept die, once the pool is sorted.
+    pub lowest_idx: u32,
+    /// Index of the highest kept die, once the pool is sorted.
+    pub highest_idx: u32,
+    /// Faces which cause another die to be rolled and added on.
+    pub explode: Vec<FaceMatch>,
+    pub explode_mode: ExplodeMode,
+    /// Faces which are rerolled until they stop showing up.
+    pub reroll: Vec<FaceMatch>,
+    /// Faces which are rerolled, but only once.
+    pub reroll_once: Vec<FaceMatch>,
+    /// If set, the dice count up successes instead of being summed.
+    pub successes: Option<Box<SuccessCount>>,
+    /// Whether every die in the pool lands on a different face, as if duplicates were rerolled.
+    pub unique: bool,
+    /// How the dice are ordered once they're rolled, rather than in the order they were rolled.
+    pub sort: Option<SortOrder>,
+}
+
+/// Which way `s`/`sa`/`sd` sort a dice pool.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Picks out the faces that a dice modifier (exploding, rerolling, ...) applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaceMatch {
+    Face(RRVal),
+    Compare(Compare, RRVal),
+}
+
+impl FaceMatch {
+    pub fn matches(&self, face: &RRVal) -> bool {
+        match self {
+            FaceMatch::Face(v) => face == v,
+            FaceMatch::Compare(cmp, v) => cmp.holds(face, v),
+        }
+    }
+
+    pub fn any(set: &[FaceMatch], face: &RRVal) -> bool {
+        set.iter().any(|m| m.matches(face))
+    }
+
+    /// Works out which faces are picked out by `cond`.
+    /// If `cond` is a single face rather than an array of faces, it's instead compared against
+    /// each face with `bare`, if given.
+    pub fn from_condition(cond: Condition<RRVal>, bare: Option<Compare>) -> Vec<FaceMatch> {
+        match cond {
+            Condition::Faces(v) => match (v, bare) {
+                (RRVal::Array(a), _) => a.into_iter().map(FaceMatch::Face).collect(),
+                (r, None) => vec![FaceMatch::Face(r)],
+                (r, Some(cmp)) => vec![FaceMatch::Compare(cmp, r)],
+            },
+            Condition::Compare(cmp, v) => vec![FaceMatch::Compare(cmp, v)],
+        }
+    }
+}
+
+/// What happens to the extra rolls when a die explodes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ExplodeMode {
+    /// `!`: each extra roll is added on, and counts as its own face for success counting.
+    #[default]
+    Standard,
+    /// `!!`: the extra rolls merge into a single value, which success counting looks at instead.
+    Compound,
+    /// `!p`: like [`ExplodeMode::Standard`], but each extra roll has 1 taken off it.
+    Penetrate,
+}
+
+/// Which faces count for or against a dice pool that counts successes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SuccessCount {
+    pub success: Vec<FaceMatch>,
+    pub failure: Vec<FaceMatch>,
+    /// Faces that count as two successes.
+    pub doubles: Vec<FaceMatch>,
+}
+
+impl SuccessCount {
+    /// How many successes a single face is worth.
+    pub fn score(&self, face: &RRVal) -> i64 {
+        let mut score = if FaceMatch::any(&self.doubles, face) {
+            2
+        } else if FaceMatch::any(&self.success, face) {
+            1
+        } else {
+            0
+        };
+        if FaceMatch::any(&self.failure, face) {
+            score -= 1;
+        }
+        score
+    }
+}
+
+impl LazyDice {
+    /// `num` dice with the given sides, and no modifiers.
+    pub fn new(num: u32, sides: Vec<RRVal>) -> Self {
+        Self {
+            num,
+            sides,
+            weights: None,
+            lowest_idx: 0,
+            highest_idx: num.saturating_sub(1),
+            explode: vec![],
+            explode_mode: ExplodeMode::Standard,
+            reroll: vec![],
+            reroll_once: vec![],
+            successes: None,
+            unique: false,
+            sort: None,
+        }
+    }
+
+    /// `num` dice whose sides come up in proportion to `weights`.
+    pub fn weighted(num: u32, sides: Vec<RRVal>, weights: Vec<u32>) -> Self {
+        Self {
+            weights: Some(weights),
+            ..Self::new(num, sides)
+        }
+    }
+
+    /// How likely each side is to come up, relative to the others.
+    pub fn side_weights(&self) -> Vec<u32> {
+        match &self.weights {
+            Some(w) => w.clone(),
+            None => vec![1; self.sides.len()],
+        }
+    }
+
+    /// How many dice survive keep/drop.
+    pub fn kept_count(&self) -> u32 {
+        (self.highest_idx + 1).saturating_sub(self.lowest_idx)
+    }
+
+    pub fn keep_highest(&mut self, n: u32) {
+        self.lowest_idx = self.lowest_idx.max(self.highest_idx.saturating_sub(n) + 1);
+    }
+
+    pub fn keep_lowest(&mut self, n: u32) {
+        self.highest_idx = self
+            .highest_idx
+            .min((self.lowest_idx + n).saturating_sub(1));
+    }
+
+    pub fn drop_highest(&mut self, n: u32) {
+        if n >= self.kept_count() {
+            self.lowest_idx = self.highest_idx + 1;
+        } else {
+            self.highest_idx -= n;
+        }
+    }
+
+    pub fn drop_lowest(&mut self, n: u32) {
+        if n >= self.kept_count() {
+            self.lowest_idx = self.highest_idx + 1;
+        } else {
+            self.lowest_idx += n;
+        }
+    }
+
+    /// Makes the dice explode on `faces`, or on their highest face if `None`.
+    pub fn explode_on(&mut self, faces: Option<Vec<FaceMatch>>, mode: ExplodeMode) {
+        match faces {
+            Some(mut faces) => self.explode.append(&mut faces),
+            None => self
+                .explode
+                .push(FaceMatch::Face(RRVal::Int(self.sides.len().into()))),
+        }
+        self.explode_mode = mode;
+    }
+
+    /// Makes sure no two dice in the pool land on the same face, if there are enough faces to go
+    /// around.
+    pub fn make_unique(&mut self) -> Result<(), String> {
+        let mut faces: Vec<_> = self
+            .sides
+            .iter()
+            .zip(self.side_weights())
+            .filter(|(_, w)| *w > 0)
+            .map(|(s, _)| s)
+            .collect();
+        faces.sort_unstable();
+        faces.dedup();
+        if self.num as usize > faces.len() {
+            return Err(format!(
+                "{} dice can't all land on different faces when there are only {}",
+                self.num,
+                faces.len()
+            ));
+        }
+        self.unique = true;
+        Ok(())
+    }
+
+    pub fn tally(&mut self, tally: Tally, mut faces: Vec<FaceMatch>) {
+        let successes = self.successes.get_or_insert_with(Box::default);
+        match tally {
+            Tally::Success => successes.success.append(&mut faces),
+            Tally::Failure => successes.failure.append(&mut faces),
+            Tally::Double => successes.doubles.append(&mut faces),
+        }
+    }
 }
 
 impl LazyValue {
-    pub async fn resolve(self, eval: &Evaluator) -> Result<RVal, ResolveError> {
+    pub async fn resolve(self, eval: &mut Evaluator) -> Result<RVal, ResolveError> {
         Ok(match self {
             LazyValue::Int(n) => RVal::Int(n),
             LazyValue::Float(f) => RVal::Float(f),
             LazyValue::Array(a) => RVal::Array(a),
             LazyValue::Char(c) => RVal::Char(c),
+            LazyValue::Bag(b) => RVal::Bag(b),
             LazyValue::Place(place) => eval.var_get(&place)?.clone().into(),
-            LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                explode,
-            } => resolve_dice(num, sides, lowest_idx, highest_idx, explode)
-                .await
-                .into(),
+            LazyValue::LazyDice(dice) => eval.roll(dice).await?.into(),
         })
     }
 
-    pub async fn deep_resolve(self, eval: &Evaluator) -> Result<RRVal, ResolveError> {
+    pub async fn deep_resolve(self, eval: &mut Evaluator) -> Result<RRVal, ResolveError> {
         Ok(match self {
             LazyValue::Int(n) => RRVal::Int(n),
             LazyValue::Float(f) => RRVal::Float(f),
             LazyValue::Array(a) => RRVal::Array(RRVal::deep_resolve_vec(a, eval).await?),
             LazyValue::Char(c) => RRVal::Char(c),
+            LazyValue::Bag(b) => RRVal::Bag(b),
             LazyValue::Place(place) => eval.var_get(&place)?.clone(),
-            LazyValue::LazyDice {
-                num,
-                sides,
-                lowest_idx,
-                highest_idx,
-                explode,
-            } => resolve_dice(num, sides, lowest_idx, highest_idx, explode).await,
+            LazyValue::LazyDice(dice) => eval.roll(dice).await?,
         })
     }
 }
@@ -1,7 +1,7 @@
 mod lazy_value;
 use std::error::Error;
 
-pub use lazy_value::LazyValue;
+pub use lazy_value::{ExplodeMode, FaceMatch, LazyDice, LazyValue, SortOrder, SuccessCount};
 
 mod rval;
 pub use rval::RVal;
@@ -9,70 +9,87 @@ pub use rval::RVal;
 mod rrval;
 pub use rrval::RRVal;
 
+mod roll;
+pub use roll::{Crit, DiceRoll, DieRoll};
+
+mod bag;
+pub use bag::{ffg_dice, ffg_sides, Bag};
+
+use crate::dice::eval::{EvalOptions, OnExplosionLimit};
 use rug::Integer;
 use smallvec::SmallVec;
 use smol_str::SmolStr;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ResolveErrorType {
-    IndexOutOfBounds,
-    IndexingIntoInvalidType,
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    /// Condition: the place must already have its index list truncated for the *last* element of
+    /// `place.indexes` to be the one out of range.
+    IndexOutOfBounds(Place),
+    IndexingIntoInvalidType(Place),
     /// Undefined variable.
-    UndefVar,
-}
-
-#[derive(Debug, Clone)]
-pub struct ResolveError {
-    /// Condition: if `ty == IndexOutOfBounds`,
-    /// `place` must already have its index list truncated for the *last* element of
-    /// `place.indexes` to be the
-    /// one out of range.
-    place: Place,
-    ty: ResolveErrorType,
+    UndefVar(Place),
+    /// A die kept exploding past the evaluation's explosion limit.
+    ExplosionLimit {
+        limit: u32,
+        die: String,
+    },
+    /// A pool has more than [`POOL_LIMIT`] dice, but isn't just a sum of numbers.
+    PoolTooBig {
+        num: u32,
+        die: String,
+    },
 }
 
 impl ResolveError {
     pub fn index_out_of_bounds(mut place: Place, ii: usize) -> Self {
         place.indexes.truncate(ii + 1);
-        Self {
-            place,
-            ty: ResolveErrorType::IndexOutOfBounds,
-        }
+        Self::IndexOutOfBounds(place)
     }
 
     pub fn index_into_invalid_type(mut place: Place, ii: usize) -> Self {
         place.indexes.truncate(ii + 1);
-        Self {
-            place,
-            ty: ResolveErrorType::IndexingIntoInvalidType,
-        }
+        Self::IndexingIntoInvalidType(place)
     }
 
     pub fn undef_var(place: Place) -> Self {
-        Self {
-            place,
-            ty: ResolveErrorType::UndefVar,
-        }
+        Self::UndefVar(place)
+    }
+
+    pub fn explosion_limit(limit: u32, die: String) -> Self {
+        Self::ExplosionLimit { limit, die }
+    }
+
+    pub fn pool_too_big(num: u32, die: String) -> Self {
+        Self::PoolTooBig { num, die }
     }
 }
 
 impl Error for ResolveError {}
 impl std::fmt::Display for ResolveError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.ty {
-            ResolveErrorType::IndexOutOfBounds => {
-                let actual_idx = self.place.indexes.last().unwrap();
-                write!(f, "Index `{actual_idx}` out of bounds in {}", &self.place)
+        match self {
+            ResolveError::IndexOutOfBounds(place) => {
+                let actual_idx = place.indexes.last().unwrap();
+                write!(f, "Index `{actual_idx}` out of bounds in {place}")
             }
-            ResolveErrorType::UndefVar => {
+            ResolveError::UndefVar(place) => {
                 write!(
                     f,
                     "Variable name {} undefined",
-                    escape_string_for_discord(&self.place.varname)
+                    escape_string_for_discord(&place.varname)
                 )
             }
-            ResolveErrorType::IndexingIntoInvalidType => {
-                write!(f, "Attempt to index into non-array type at {}", &self.place)
+            ResolveError::IndexingIntoInvalidType(place) => {
+                write!(f, "Attempt to index into non-array type at {place}")
+            }
+            ResolveError::ExplosionLimit { limit, die } => {
+                write!(f, "explosion limit {limit} reached on die {die}")
+            }
+            ResolveError::PoolTooBig { num, die } => {
+                write!(
+                    f,
+                    "too many dice: {num}{die} > {POOL_LIMIT}, and only plain sums of numbers can go past that"
+                )
             }
         }
     }
@@ -100,81 +117,263 @@ pub fn norm_float(f: f64) -> f64 {
     (f * 1_000_000.) / (1_000_000.)
 }
 
-pub async fn resolve_dice(
-    num: u32,
-    sides: Vec<RRVal>,
-    lowest_idx: u32,
-    highest_idx: u32,
-    explode: Vec<RRVal>,
-) -> RRVal {
-    if sides.is_empty() || highest_idx < lowest_idx {
-        return RRVal::Int(Integer::ZERO);
-    }
-    use rand::distributions::{Distribution, Uniform};
-    let mut rng = crate::dice::get_rng();
-    let between = Uniform::from(0..sides.len());
-    async fn do_explode(
-        rng: &mut (impl rand::Rng + Send),
-        sides: &[RRVal],
-        between: &Uniform<usize>,
-        explode: &[RRVal],
-    ) -> RRVal {
-        let mut sum: Option<RRVal> = None;
+/// Sums `vals` in order, or returns `None` if there's nothing to sum.
+pub async fn sum_rrvals(vals: impl IntoIterator<Item = RRVal>) -> Option<RRVal> {
+    let mut sum: Option<RRVal> = None;
+    for v in vals {
+        if let Some(s) = sum {
+            sum = Some(s.add(v).await);
+        } else {
+            sum = Some(v);
+        }
+    }
+    sum
+}
+
+/// The most dice rolled one at a time. Bigger pools only get their total sampled, which only works
+/// if they're just summed up.
+pub const POOL_LIMIT: u32 = 65535;
+
+/// Maximum number of times a single face can be rerolled by `r`, so that e.g. `d1r1` terminates.
+pub const REROLL_LIMIT: u32 = 100;
+
+/// How a die with these sides is written, e.g. `d6` or `d[1, 1]`.
+fn die_name(sides: &[RRVal]) -> String {
+    let standard = sides
+        .iter()
+        .enumerate()
+        .all(|(i, s)| matches!(s, RRVal::Int(n) if *n == i + 1));
+    if standard {
+        format!("d{}", sides.len())
+    } else {
+        format!("d{}", RRVal::Array(sides.to_vec()))
+    }
+}
+
+/// The lowest face of a d20 that's a critical success, if `crit_range` isn't 0, and the face
+/// that's a fumble, or `None` if the die isn't a d20. Highest and lowest faces on other dice, like
+/// a 6 on a damage die or a -1 on a Fate die, aren't anything special.
+fn crit_faces(sides: &[RRVal], crit_range: u32) -> Option<(Option<&RRVal>, &RRVal)> {
+    let mut faces: Vec<_> = sides.iter().collect();
+    faces.sort_unstable();
+    let d20 = faces.len() == 20
+        && faces
+            .iter()
+            .enumerate()
+            .all(|(i, s)| matches!(s, RRVal::Int(n) if *n == i + 1));
+    if !d20 {
+        return None;
+    }
+    // a 1 is never a crit, however wide the range
+    let crit =
+        (crit_range > 0).then(|| faces[faces.len().saturating_sub(crit_range as usize).max(1)]);
+    Some((crit, faces[0]))
+}
+
+pub async fn resolve_dice<R: rand::Rng>(
+    dice: LazyDice,
+    options: &EvalOptions,
+    rng: &mut R,
+) -> Result<DiceRoll, ResolveError> {
+    let side_weights = dice.side_weights();
+    let LazyDice {
+        num,
+        sides,
+        weights,
+        lowest_idx,
+        highest_idx,
+        explode,
+        explode_mode,
+        reroll,
+        reroll_once,
+        successes,
+        unique,
+        sort,
+    } = dice;
+    if sides.is_empty() {
+        return Ok(DiceRoll {
+            dice: vec![],
+            unlisted: 0,
+            total: RRVal::Int(Integer::ZERO),
+        });
+    }
+    if num > POOL_LIMIT {
+        let plain = lowest_idx == 0
+            && highest_idx == num - 1
+            && explode.is_empty()
+            && reroll.is_empty()
+            && reroll_once.is_empty()
+            && successes.is_none();
+        return match sample_sum(num, &sides, &side_weights, rng) {
+            Some(total) if plain => Ok(DiceRoll {
+                dice: vec![],
+                unlisted: num,
+                total,
+            }),
+            _ => Err(ResolveError::pool_too_big(num, die_name(&sides))),
+        };
+    }
+    use rand::distributions::{Distribution, Uniform, WeightedIndex};
+    let uniform = Uniform::from(0..sides.len());
+    let weighted = weights.map(|w| WeightedIndex::new(w).expect("dice weights are checked"));
+    let between = |rng: &mut R| match &weighted {
+        Some(w) => w.sample(rng),
+        None => uniform.sample(rng),
+    };
+    let crit_faces = crit_faces(&sides, options.crit_range);
+    // the sides that the first face of the next die can still land on, if the pool is unique
+    let mut free: Option<Vec<usize>> =
+        unique.then(|| (0..sides.len()).filter(|&i| side_weights[i] > 0).collect());
+    let mut dice = Vec::new();
+    dice.reserve_exact(num as usize);
+    for _ in 0..num {
+        let mut faces = Vec::new();
+        let mut rerolled = Vec::new();
         /* do-while loop, cough cough... */
         while {
-            let i = between.sample(rng);
-            let x = &sides[i];
-            if let Some(s) = sum {
-                sum = Some(s.add(x.clone()).await);
+            let first = faces.is_empty();
+            let roll_side = |rng: &mut R| match &free {
+                Some(free) if first => pick_free(free, &side_weights, rng),
+                _ => between(rng),
+            };
+            let mut x = &sides[roll_side(rng)];
+            let mut rerolls = 0;
+            while (rerolls < REROLL_LIMIT && FaceMatch::any(&reroll, x))
+                || (rerolls == 0 && FaceMatch::any(&reroll_once, x))
+            {
+                rerolled.push(x.clone());
+                rerolls += 1;
+                x = &sides[roll_side(rng)];
+            }
+            if explode_mode == ExplodeMode::Penetrate && !faces.is_empty() {
+                faces.push(x.clone().sub(RRVal::Int(1.into())).await);
             } else {
-                sum = Some(x.clone());
+                faces.push(x.clone());
+            }
+            let explodes = FaceMatch::any(&explode, x);
+            if explodes && faces.len() > options.explosion_limit as usize {
+                match options.on_explosion_limit {
+                    OnExplosionLimit::Error => {
+                        return Err(ResolveError::explosion_limit(
+                            options.explosion_limit,
+                            die_name(&sides),
+                        ))
+                    }
+                    OnExplosionLimit::Cap => false,
+                }
+            } else {
+                explodes
             }
-            explode.contains(x)
         } {
             // just in case.
             crate::util::yield_point().await;
         }
-        sum.unwrap_or(RRVal::Int(Integer::ZERO))
+        let value = sum_rrvals(faces.iter().cloned())
+            .await
+            .unwrap_or(RRVal::Int(Integer::ZERO));
+        let crit = crit_faces.and_then(|(crit, fumble)| match &faces[0] {
+            x if crit.is_some_and(|crit| x >= crit) => Some(Crit::Success),
+            x if x == fumble => Some(Crit::Fumble),
+            _ => None,
+        });
+        if let Some(free) = &mut free {
+            free.retain(|&i| sides[i] != faces[0]);
+        }
+        dice.push(DieRoll {
+            faces,
+            rerolled,
+            value,
+            kept: false,
+            crit,
+        });
     }
-    if lowest_idx == 0 && highest_idx == num - 1 {
-        let mut sum: Option<RRVal> = None;
-        for _ in 0..num {
-            let sample = if !explode.is_empty() {
-                do_explode(&mut rng, &sides, &between, &explode).await
-            } else {
-                let i = between.sample(&mut rng);
-                sides[i].clone()
-            };
-            if let Some(s) = sum {
-                sum = Some(s.add(sample).await);
-            } else {
-                sum = Some(sample);
-            }
+    if lowest_idx <= highest_idx {
+        let mut order: Vec<_> = (0..dice.len()).collect();
+        order.sort_by(|a, b| dice[*a].value.cmp(&dice[*b].value));
+        for i in order
+            .into_iter()
+            .skip(lowest_idx as usize)
+            .take((highest_idx - lowest_idx) as usize + 1)
+        {
+            dice[i].kept = true;
         }
-        sum.unwrap_or(RRVal::Int(Integer::ZERO))
+    }
+    match sort {
+        Some(SortOrder::Ascending) => dice.sort_by(|a, b| a.value.cmp(&b.value)),
+        Some(SortOrder::Descending) => dice.sort_by(|a, b| b.value.cmp(&a.value)),
+        None => (),
+    }
+    let total = if let Some(successes) = successes {
+        let count: i64 = if explode_mode == ExplodeMode::Compound {
+            dice.iter()
+                .filter(|d| d.kept)
+                .map(|d| successes.score(&d.value))
+                .sum()
+        } else {
+            dice.iter()
+                .filter(|d| d.kept)
+                .flat_map(|d| &d.faces)
+                .map(|face| successes.score(face))
+                .sum()
+        };
+        RRVal::Int(count.into())
     } else {
-        let mut res = Vec::new();
-        res.reserve_exact(num as usize);
-        for _ in 0..num {
-            let sample = if !explode.is_empty() {
-                do_explode(&mut rng, &sides, &between, &explode).await
-            } else {
-                let i = between.sample(&mut rng);
-                sides[i].clone()
-            };
-            res.push(sample);
-        }
-        res.sort_unstable();
-        let mut sum: Option<RRVal> = None;
-        for item in res.drain(lowest_idx as usize..=highest_idx as usize) {
-            if let Some(s) = sum {
-                sum = Some(s.add(item).await);
-            } else {
-                sum = Some(item);
-            }
+        let kept: Vec<_> = dice
+            .iter()
+            .filter(|d| d.kept)
+            .map(|d| d.value.clone())
+            .collect();
+        sum_rrvals(kept).await.unwrap_or(RRVal::Int(Integer::ZERO))
+    };
+    Ok(DiceRoll {
+        dice,
+        unlisted: 0,
+        total,
+    })
+}
+
+/// Picks one of the `free` sides, in proportion to their weights.
+fn pick_free<R: rand::Rng>(free: &[usize], weights: &[u32], rng: &mut R) -> usize {
+    use rand::distributions::{Distribution, WeightedIndex};
+    let w = WeightedIndex::new(free.iter().map(|&i| weights[i]))
+        .expect("unique pools have a side left for every die");
+    free[w.sample(rng)]
+}
+
+/// The total of `num` dice with numeric `sides`, sampled by working out how many dice land on each
+/// face rather than rolling them one at a time, or `None` if a side isn't a number.
+fn sample_sum(
+    num: u32,
+    sides: &[RRVal],
+    weights: &[u32],
+    rng: &mut impl rand::Rng,
+) -> Option<RRVal> {
+    use rand::distributions::Distribution;
+    use rand_distr::Binomial;
+    let mut ints = Integer::ZERO;
+    let mut floats: Option<f64> = None;
+    let mut left = u64::from(num);
+    let mut weight_left: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+    for (face, &weight) in sides.iter().zip(weights) {
+        // out of the dice that didn't land on an earlier face, how many land on this one
+        let count = if u64::from(weight) == weight_left {
+            left
+        } else {
+            let p = f64::from(weight) / weight_left as f64;
+            Binomial::new(left, p).unwrap().sample(rng)
+        };
+        left -= count;
+        weight_left -= u64::from(weight);
+        match face {
+            RRVal::Int(n) => ints += Integer::from(n * count),
+            RRVal::Float(f) => *floats.get_or_insert(0.) += f * count as f64,
+            _ => return None,
         }
-        sum.unwrap_or(RRVal::Int(Integer::ZERO))
     }
+    Some(match floats {
+        Some(f) => RRVal::Float(ints.to_f64() + f),
+        None => RRVal::Int(ints),
+    })
 }
 
 impl From<i32> for LazyValue {
@@ -211,6 +410,23 @@ pub fn array_take_most_extreme_n(mut a: Vec<RRVal>, n: usize, lower_half: bool)
     new_vals
 }
 
+/// The `n` highest (or lowest, if `lowest` is true) elements of `vals`, in sorted order.
+pub fn array_keep(vals: Vec<RRVal>, n: usize, lowest: bool) -> Vec<RRVal> {
+    if n == 0 {
+        vec![]
+    } else if n >= vals.len() {
+        vals
+    } else {
+        array_take_most_extreme_n(vals, n, lowest)
+    }
+}
+
+/// Everything but the `n` highest (or lowest, if `lowest` is true) elements of `vals`.
+pub fn array_drop(vals: Vec<RRVal>, n: usize, lowest: bool) -> Vec<RRVal> {
+    let len = vals.len();
+    array_keep(vals, len.saturating_sub(n), !lowest)
+}
+
 pub fn escape_string_for_discord_inplace(inp: &str, s: &mut String) {
     s.push_str("``"); // outer code formatting to prevent abusing mentions
     s.push('"');
@@ -0,0 +1,167 @@
+use super::RRVal;
+
+/// A die whose natural roll was at the top or bottom of its range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crit {
+    /// Within [`EvalOptions::crit_range`](crate::dice::EvalOptions::crit_range) of a d20's
+    /// highest face, like a natural 20.
+    Success,
+    /// A natural 1 on a d20.
+    Fumble,
+}
+
+/// A single die out of a [`DiceRoll`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DieRoll {
+    /// Every face rolled for this die, in the order they were rolled.
+    /// There's more than one face only if the die exploded.
+    pub faces: Vec<RRVal>,
+    /// Faces that were rolled but then thrown away by rerolling, in the order they were rolled.
+    pub rerolled: Vec<RRVal>,
+    /// The sum of `faces`.
+    pub value: RRVal,
+    /// Whether this die survived keep-highest/keep-lowest.
+    pub kept: bool,
+    /// Whether the first face rolled was a crit, for d20s that aren't rolled by `check(...)`.
+    pub crit: Option<Crit>,
+}
+
+impl DieRoll {
+    pub fn exploded(&self) -> bool {
+        self.faces.len() > 1
+    }
+}
+
+impl std::fmt::Display for DieRoll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.kept {
+            write!(f, "~~")?;
+        }
+        for face in &self.rerolled {
+            write!(f, "{face}↻")?;
+        }
+        let mut faces = self.faces.iter().enumerate().peekable();
+        while let Some((i, face)) = faces.next() {
+            if i == 0 && self.crit.is_some() {
+                write!(f, "**{face}**")?;
+            } else {
+                write!(f, "{face}")?;
+            }
+            if faces.peek().is_some() {
+                // this face made the die explode
+                write!(f, "!+")?;
+            }
+        }
+        if !self.kept {
+            write!(f, "~~")?;
+        }
+        Ok(())
+    }
+}
+
+/// The breakdown of a single resolution of a [`LazyValue::LazyDice`](super::LazyValue::LazyDice).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceRoll {
+    /// The dice, in the order they were rolled.
+    pub dice: Vec<DieRoll>,
+    /// How many dice there were in a pool too big to roll one at a time, which only had its total
+    /// sampled. Such pools have no `dice`.
+    pub unlisted: u32,
+    /// The sum of all kept dice.
+    pub total: RRVal,
+}
+
+impl DiceRoll {
+    pub fn kept(&self) -> impl Iterator<Item = &DieRoll> {
+        self.dice.iter().filter(|d| d.kept)
+    }
+
+    pub fn dropped(&self) -> impl Iterator<Item = &DieRoll> {
+        self.dice.iter().filter(|d| !d.kept)
+    }
+}
+
+impl std::fmt::Display for DiceRoll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.unlisted > 0 {
+            return write!(f, "[{} dice]", self.unlisted);
+        }
+        write!(f, "[")?;
+        let mut i = self.dice.iter();
+        if let Some(d) = i.next() {
+            write!(f, "{d}")?;
+            for d in i {
+                write!(f, ", {d}")?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[test]
+fn display_test() {
+    let die = |faces: Vec<i32>, kept| DieRoll {
+        value: faces.iter().sum::<i32>().into(),
+        faces: faces.into_iter().map(RRVal::from).collect(),
+        rerolled: vec![],
+        kept,
+        crit: None,
+    };
+    let roll = DiceRoll {
+        dice: vec![
+            die(vec![6], true),
+            die(vec![5], true),
+            die(vec![1], false),
+            die(vec![3], true),
+        ],
+        unlisted: 0,
+        total: 14.into(),
+    };
+    assert_eq!(format!("{roll}"), "[6, 5, ~~1~~, 3]");
+    let roll = DiceRoll {
+        dice: vec![die(vec![2, 2, 1], true), die(vec![2, 1], false)],
+        unlisted: 0,
+        total: 5.into(),
+    };
+    assert_eq!(format!("{roll}"), "[2!+2!+1, ~~2!+1~~]");
+    let roll = DiceRoll {
+        dice: vec![DieRoll {
+            rerolled: vec![1.into(), 2.into()],
+            ..die(vec![5], true)
+        }],
+        unlisted: 0,
+        total: 5.into(),
+    };
+    assert_eq!(format!("{roll}"), "[1↻2↻5]");
+    let roll = DiceRoll {
+        dice: vec![
+            DieRoll {
+                crit: Some(Crit::Success),
+                ..die(vec![20], true)
+            },
+            DieRoll {
+                crit: Some(Crit::Fumble),
+                ..die(vec![1], false)
+            },
+            DieRoll {
+                crit: Some(Crit::Success),
+                ..die(vec![6, 2], true)
+            },
+        ],
+        unlisted: 0,
+        total: 28.into(),
+    };
+    assert_eq!(format!("{roll}"), "[**20**, ~~**1**~~, **6**!+2]");
+    let roll = DiceRoll {
+        dice: vec![],
+        unlisted: 0,
+        total: 0.into(),
+    };
+    assert_eq!(format!("{roll}"), "[]");
+    let roll = DiceRoll {
+        dice: vec![],
+        unlisted: 1000000,
+        total: 3500000.into(),
+    };
+    assert_eq!(format!("{roll}"), "[1000000 dice]");
+}
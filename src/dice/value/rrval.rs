@@ -6,7 +6,7 @@ use rug::Integer;
 
 use crate::dice::{eval::Evaluator, value::norm_float};
 
-use super::{escape_string_for_discord, LazyValue, RVal, ResolveError};
+use super::{escape_string_for_discord, Bag, LazyValue, RVal, ResolveError};
 
 macro_rules! dimensional_broadcast {
     ($a:ident, $f:expr, $b:ident) => {
@@ -57,6 +57,7 @@ pub enum RRVal {
     Float(f64),
     Array(Vec<RRVal>),
     Char(char),
+    Bag(Bag),
 }
 
 impl From<RRVal> for RVal {
@@ -66,6 +67,7 @@ impl From<RRVal> for RVal {
             RRVal::Float(f) => RVal::Float(f),
             RRVal::Char(c) => RVal::Char(c),
             RRVal::Array(a) => RVal::Array(a.into_iter().map(|x| x.into()).collect()),
+            RRVal::Bag(b) => RVal::Bag(b),
         }
     }
 }
@@ -77,6 +79,7 @@ impl From<RRVal> for LazyValue {
             RRVal::Float(f) => LazyValue::Float(f),
             RRVal::Char(c) => LazyValue::Char(c),
             RRVal::Array(a) => LazyValue::Array(a.into_iter().map(|x| x.into()).collect()),
+            RRVal::Bag(b) => LazyValue::Bag(b),
         }
     }
 }
@@ -85,7 +88,7 @@ impl RRVal {
     #[async_recursion]
     pub async fn deep_resolve_vec(
         a: Vec<LazyValue>,
-        eval: &Evaluator,
+        eval: &mut Evaluator,
     ) -> Result<Vec<RRVal>, ResolveError> {
         let mut v = Vec::new();
         v.reserve_exact(a.len());
@@ -115,6 +118,12 @@ impl RRVal {
             (RRVal::Float(f), RRVal::Char(c)) | (RRVal::Char(c), RRVal::Float(f)) => {
                 RRVal::Float(f + (c as u32 as f64))
             }
+            (RRVal::Bag(a), RRVal::Bag(b)) => RRVal::Bag(a.union(b)),
+            // a number of symbols doesn't say which symbols to add
+            (RRVal::Bag(_), RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_))
+            | (RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_), RRVal::Bag(_)) => {
+                RRVal::Float(f64::NAN)
+            }
             (RRVal::Array(mut a), RRVal::Array(b)) => {
                 dimensional_broadcast!(a, RRVal::add, b).await
             }
@@ -140,6 +149,11 @@ impl RRVal {
             (RRVal::Int(n), RRVal::Char(c)) => RRVal::Int(n - (c as u32)),
             (RRVal::Float(f), RRVal::Char(c)) => RRVal::Float(f - (c as u32 as f64)),
             (RRVal::Char(c), RRVal::Float(f)) => RRVal::Float((c as u32 as f64) - f),
+            (RRVal::Bag(a), RRVal::Bag(b)) => RRVal::Bag(a.difference(&b)),
+            (RRVal::Bag(_), RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_))
+            | (RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_), RRVal::Bag(_)) => {
+                RRVal::Float(f64::NAN)
+            }
             (RRVal::Array(mut a), RRVal::Array(b)) => {
                 dimensional_broadcast!(a, RRVal::sub, b).await
             }
@@ -165,6 +179,12 @@ impl RRVal {
             (RRVal::Float(f), RRVal::Char(c)) | (RRVal::Char(c), RRVal::Float(f)) => {
                 RRVal::Float(f * (c as u32 as f64))
             }
+            (RRVal::Bag(a), RRVal::Int(n)) | (RRVal::Int(n), RRVal::Bag(a)) => match n.to_u32() {
+                Some(n) => RRVal::Bag(a.times(n)),
+                None => RRVal::Float(f64::NAN),
+            },
+            (RRVal::Bag(_), RRVal::Float(_) | RRVal::Char(_) | RRVal::Bag(_))
+            | (RRVal::Float(_) | RRVal::Char(_), RRVal::Bag(_)) => RRVal::Float(f64::NAN),
             (RRVal::Array(mut a), RRVal::Array(b)) => {
                 dimensional_broadcast!(a, RRVal::mul, b).await
             }
@@ -185,6 +205,10 @@ impl RRVal {
             (RRVal::Int(n), RRVal::Char(c)) => RRVal::Float(n.az::<f64>() / c as u32 as f64),
             (RRVal::Float(f), RRVal::Char(c)) => RRVal::Float(f / c as u32 as f64),
             (RRVal::Char(c), RRVal::Float(f)) => RRVal::Float((c as u32 as f64) / f),
+            (RRVal::Bag(_), RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_) | RRVal::Bag(_))
+            | (RRVal::Int(_) | RRVal::Float(_) | RRVal::Char(_), RRVal::Bag(_)) => {
+                RRVal::Float(f64::NAN)
+            }
             (RRVal::Array(mut a), RRVal::Array(b)) => {
                 dimensional_broadcast!(a, RRVal::fdiv, b).await
             }
@@ -229,6 +253,30 @@ impl RRVal {
         }
     }
 
+    #[async_recursion]
+    pub async fn op_le(self, other: RRVal) -> RRVal {
+        match (self, other) {
+            (RRVal::Array(mut a), RRVal::Array(b)) => {
+                dimensional_broadcast!(a, RRVal::op_le, b).await
+            }
+            (RRVal::Array(mut a), v) => broadcast!(#a, RRVal::op_le, v).await,
+            (v, RRVal::Array(mut a)) => broadcast!(v, RRVal::op_le, #a).await,
+            (l, r) => RRVal::Int((cmp_rrvals(&l, &r) != Ordering::Greater).into()),
+        }
+    }
+
+    #[async_recursion]
+    pub async fn op_ge(self, other: RRVal) -> RRVal {
+        match (self, other) {
+            (RRVal::Array(mut a), RRVal::Array(b)) => {
+                dimensional_broadcast!(a, RRVal::op_ge, b).await
+            }
+            (RRVal::Array(mut a), v) => broadcast!(#a, RRVal::op_ge, v).await,
+            (v, RRVal::Array(mut a)) => broadcast!(v, RRVal::op_ge, #a).await,
+            (l, r) => RRVal::Int((cmp_rrvals(&l, &r) != Ordering::Less).into()),
+        }
+    }
+
     #[async_recursion]
     pub async fn neg(self) -> RRVal {
         match self {
@@ -244,6 +292,7 @@ impl RRVal {
                 RRVal::Array(a)
             }
             RRVal::Char(c) => RRVal::Int((-(c as i32)).into()),
+            RRVal::Bag(_) => RRVal::Float(f64::NAN),
         }
     }
 
@@ -271,6 +320,7 @@ impl RRVal {
             RRVal::Float(f) => *f != 0.,
             RRVal::Array(a) => !a.is_empty(),
             RRVal::Char(c) => *c != '\0',
+            RRVal::Bag(b) => !b.is_empty(),
         }
     }
 
@@ -313,6 +363,7 @@ impl std::fmt::Display for RRVal {
                 }
             }
             RRVal::Char(c) => write!(f, "'{c}'"),
+            RRVal::Bag(b) => write!(f, "{b}"),
         }
     }
 }
@@ -429,6 +480,10 @@ pub fn cmp_rrvals(lhs: &RRVal, rhs: &RRVal) -> std::cmp::Ordering {
                 Greater
             }
         }
+        (RRVal::Bag(a), RRVal::Bag(b)) => a.cmp(b),
+        // bags are above every number
+        (RRVal::Bag(_), _) => Greater,
+        (_, RRVal::Bag(_)) => Less,
     }
 }
 
@@ -1,4 +1,4 @@
-use super::LazyValue;
+use super::{Bag, LazyValue};
 use rug::Integer;
 
 /// Resolved version of [`Value`].
@@ -8,6 +8,7 @@ pub enum RVal {
     Float(f64),
     Array(Vec<LazyValue>),
     Char(char),
+    Bag(Bag),
 }
 
 impl RVal {
@@ -30,6 +31,7 @@ impl RVal {
             }
             RVal::Array(_) => Err("cannot cast array to integer".to_string()),
             RVal::Char(c) => Ok(c as i32),
+            RVal::Bag(_) => Err("cannot cast a bag of symbols to integer".to_string()),
         }
     }
 }
@@ -68,6 +70,7 @@ impl From<RVal> for LazyValue {
             RVal::Float(f) => LazyValue::Float(f),
             RVal::Array(a) => LazyValue::Array(a),
             RVal::Char(c) => LazyValue::Char(c),
+            RVal::Bag(b) => LazyValue::Bag(b),
         }
     }
 }
@@ -1,3 +1,5 @@
+pub mod chart;
 pub mod dice;
 pub mod os;
+pub mod table;
 pub mod util;
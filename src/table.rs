@@ -0,0 +1,446 @@
+//! Random tables, like encounters or loot, loaded from text files in a directory.
+//!
+//! Each `.txt` file is a table named after the file. Every line is an entry, optionally led by the
+//! range of rolls it's for:
+//!
+//! ```text
+//! # Forest encounters
+//! dice: d100
+//! 01-15 Goblins
+//! 16-30 [[2d4]] wolves
+//! 31-00 A traveller carrying {{loot}}
+//! ```
+//!
+//! Lines starting with `#` are comments. The `dice:` line is optional, and defaults to a die with
+//! as many sides as the highest roll, so it's only needed for tables like `2-12` on 2d6. A table
+//! without any ranges gives each line the same chance. `00` is 100, as on percentile dice.
+//! `[[2d4]]` is rolled like `%roll` does, and `{{loot}}` rolls on the table `loot`.
+//!
+//! Lines led by single numbers are only numbered entries if they count up from 1, or there's a
+//! `dice:` line. Otherwise, like in `10 gold` and `50 silver`, the numbers are part of the text.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use async_recursion::async_recursion;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use tokio::fs;
+
+use crate::dice::{self, value::RRVal};
+
+/// How deep tables can roll on other tables, which also stops tables that refer to each other
+/// from going around in circles forever.
+const TABLE_DEPTH_LIMIT: u32 = 10;
+
+/// The directory tables are loaded from: `$TABLES_DIR`, or `tables` if that isn't set.
+pub fn tables_dir() -> PathBuf {
+    std::env::var_os("TABLES_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("tables"))
+}
+
+/// One line of a table, for rolls from `low` to `high`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub low: i32,
+    pub high: i32,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    /// The expression rolled to pick an entry.
+    pub dice: String,
+    /// The entries, in order of their ranges, which don't overlap or leave gaps.
+    pub entries: Vec<Entry>,
+}
+
+/// Parses `01-15`, `16` or `91-00`.
+fn parse_range(s: &str) -> Option<(i32, i32)> {
+    let num = |s: &str| -> Option<i32> {
+        if !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        match s.parse().ok()? {
+            0 if s.len() > 1 => Some(100),
+            n => Some(n),
+        }
+    };
+    match s.split_once(['-', '–']) {
+        Some((low, high)) => Some((num(low)?, num(high)?)),
+        None => num(s).map(|n| (n, n)),
+    }
+}
+
+impl Table {
+    /// Parses the table `name` from the text of its file.
+    pub fn parse(name: &str, src: &str) -> anyhow::Result<Self> {
+        let mut dice = None;
+        let mut ranged = Vec::new();
+        let mut plain = Vec::new();
+        let mut lines = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(expr) = line.strip_prefix("dice:") {
+                dice = Some(expr.trim().to_string());
+                continue;
+            }
+            lines.push(line.to_string());
+            let (head, text) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match parse_range(head) {
+                Some((low, high)) if low <= high => ranged.push(Entry {
+                    low,
+                    high,
+                    text: text.trim().to_string(),
+                }),
+                Some(_) => anyhow::bail!("table {name}, line {}: backwards range {head}", i + 1),
+                None => plain.push(line.to_string()),
+            }
+        }
+        if dice.is_none() && ranged.iter().all(|e| e.low == e.high) {
+            let mut numbers: Vec<_> = ranged.iter().map(|e| e.low).collect();
+            numbers.sort_unstable();
+            if !plain.is_empty() || !numbers.iter().copied().eq(1..=numbers.len() as i32) {
+                ranged.clear();
+                plain = lines;
+            }
+        }
+        let mut entries = match (ranged.is_empty(), plain.is_empty()) {
+            (true, true) => anyhow::bail!("table {name} has no entries"),
+            (false, true) => ranged,
+            (true, false) => (1..)
+                .zip(plain)
+                .map(|(n, text)| Entry {
+                    low: n,
+                    high: n,
+                    text,
+                })
+                .collect(),
+            (false, false) => anyhow::bail!(
+                "table {name} has lines with ranges and lines without, like `{}`",
+                plain[0]
+            ),
+        };
+        entries.sort_by_key(|e| e.low);
+        for pair in entries.windows(2) {
+            if pair[1].low <= pair[0].high {
+                anyhow::bail!("table {name} has more than one entry for {}", pair[1].low);
+            }
+            if pair[1].low > pair[0].high + 1 {
+                anyhow::bail!("table {name} has no entry for {}", pair[0].high + 1);
+            }
+        }
+        let (first, last) = (&entries[0], &entries[entries.len() - 1]);
+        let dice = match dice {
+            Some(dice) => dice,
+            None if first.low == 1 => format!("d{}", last.high),
+            None => anyhow::bail!(
+                "table {name} starts at {}, so it needs a `dice:` line to say what to roll",
+                first.low
+            ),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            dice,
+            entries,
+        })
+    }
+
+    /// The entry for a roll of `n`.
+    pub fn entry(&self, n: i32) -> Option<&Entry> {
+        self.entries.iter().find(|e| (e.low..=e.high).contains(&n))
+    }
+}
+
+/// What rolling on a table came up with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRoll {
+    pub table: String,
+    /// What was rolled on the table's dice.
+    pub roll: RRVal,
+    /// The entry rolled, with any dice and tables in it rolled too.
+    pub text: String,
+}
+
+/// A collection of tables that can roll on each other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tables {
+    /// The tables, by their lowercased names.
+    tables: BTreeMap<String, Table>,
+    /// Why each file that couldn't be loaded as a table wasn't, by its lowercased table name.
+    problems: BTreeMap<String, String>,
+}
+
+impl Tables {
+    pub fn new(tables: impl IntoIterator<Item = Table>) -> Self {
+        Self {
+            tables: tables
+                .into_iter()
+                .map(|t| (t.name.to_lowercase(), t))
+                .collect(),
+            problems: BTreeMap::new(),
+        }
+    }
+
+    /// Loads every `.txt` file in `dir` as a table. A missing directory has no tables in it.
+    /// Files that can't be read or aren't valid tables are left out, and listed in
+    /// [`Tables::problems`].
+    pub async fn load(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        if !fs::try_exists(dir).await? {
+            return Ok(Self::default());
+        }
+        let mut tables = Vec::new();
+        let mut problems = BTreeMap::new();
+        let mut files = fs::read_dir(dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let table = match fs::read_to_string(&path).await {
+                    Ok(src) => Table::parse(name, &src),
+                    // without the path, since the bot shows these to its users
+                    Err(e) => Err(anyhow::anyhow!("couldn't read table {name}: {e}")),
+                };
+                match table {
+                    Ok(table) => tables.push(table),
+                    Err(e) => {
+                        problems.insert(name.to_lowercase(), e.to_string());
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            problems,
+            ..Self::new(tables)
+        })
+    }
+
+    /// Why files in the directory couldn't be loaded as tables, one per file.
+    pub fn problems(&self) -> impl Iterator<Item = &str> {
+        self.problems.values().map(String::as_str)
+    }
+
+    /// The table called `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Table> {
+        self.tables.get(&name.to_lowercase())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tables.values().map(|t| t.name.as_str())
+    }
+
+    /// Rolls on the table called `name`, and on any tables its entry refers to. The same `seed`
+    /// always rolls the same; a random one is picked if it's `None`.
+    pub async fn roll(&self, name: &str, seed: Option<u64>) -> anyhow::Result<TableRoll> {
+        let mut seeds = ChaCha12Rng::seed_from_u64(seed.unwrap_or_else(rand::random));
+        self.roll_nested(name, 0, &mut seeds).await
+    }
+
+    #[async_recursion]
+    async fn roll_nested(
+        &self,
+        name: &str,
+        depth: u32,
+        seeds: &mut ChaCha12Rng,
+    ) -> anyhow::Result<TableRoll> {
+        if depth > TABLE_DEPTH_LIMIT {
+            anyhow::bail!("tables refer to each other more than {TABLE_DEPTH_LIMIT} deep");
+        }
+        let Some(table) = self.get(name) else {
+            if let Some(problem) = self.problems.get(&name.to_lowercase()) {
+                anyhow::bail!("{problem}");
+            }
+            anyhow::bail!("there's no table called {name}");
+        };
+        let roll = eval(&table.dice, seeds).await?;
+        let n = roll
+            .clone()
+            .into_i32()
+            .map_err(|e| anyhow::anyhow!("table {} rolled {roll}: {e}", table.name))?;
+        let Some(entry) = table.entry(n) else {
+            anyhow::bail!("table {} has no entry for {n}", table.name);
+        };
+        Ok(TableRoll {
+            table: table.name.clone(),
+            roll,
+            text: self.expand(&entry.text, depth, seeds).await?,
+        })
+    }
+
+    /// Rolls the `[[dice]]` and `{{tables}}` in `text`.
+    async fn expand(
+        &self,
+        text: &str,
+        depth: u32,
+        seeds: &mut ChaCha12Rng,
+    ) -> anyhow::Result<String> {
+        let mut res = String::new();
+        let mut rest = text;
+        while let Some(start) = [rest.find("[["), rest.find("{{")]
+            .into_iter()
+            .flatten()
+            .min()
+        {
+            let close = if rest[start..].starts_with("[[") {
+                "]]"
+            } else {
+                "}}"
+            };
+            let Some(len) = rest[start + 2..].find(close) else {
+                anyhow::bail!("unclosed `{}` in `{text}`", &rest[start..start + 2]);
+            };
+            res.push_str(&rest[..start]);
+            let inner = rest[start + 2..start + 2 + len].trim();
+            if close == "]]" {
+                res.push_str(&entry_text(eval(inner, seeds).await?));
+            } else {
+                res.push_str(&self.roll_nested(inner, depth + 1, seeds).await?.text);
+            }
+            rest = &rest[start + 2 + len + 2..];
+        }
+        res.push_str(rest);
+        Ok(res)
+    }
+}
+
+/// How the result of `[[...]]` is written in an entry: strings and characters as they are, without
+/// the quotes `%roll` puts around them, and anything else like `%roll` writes it.
+fn entry_text(v: RRVal) -> String {
+    match v {
+        RRVal::Char(c) => c.to_string(),
+        RRVal::Array(arr) if !arr.is_empty() && arr.iter().all(|x| matches!(x, RRVal::Char(_))) => {
+            arr.iter()
+                .map(|x| match x {
+                    RRVal::Char(c) => *c,
+                    _ => unreachable!("we checked these were all characters already"),
+                })
+                .collect()
+        }
+        v => v.to_string(),
+    }
+}
+
+/// Rolls `expr` with the next seed from `seeds`.
+async fn eval(expr: &str, seeds: &mut ChaCha12Rng) -> anyhow::Result<RRVal> {
+    let options = dice::EvalOptions {
+        seed: Some(seeds.next_u64()),
+        ..Default::default()
+    };
+    Ok(dice::eval_with(expr, options).await?.value)
+}
+
+#[tokio::test]
+async fn table_test() {
+    let encounters = Table::parse(
+        "Encounters",
+        "# forest\n01-50 [[2]] wolves\n51–99 {{loot}}\n00 A dragon with {{ Loot }}\n",
+    )
+    .unwrap();
+    assert_eq!(encounters.dice, "d100");
+    assert_eq!(
+        encounters.entry(100).unwrap().text,
+        "A dragon with {{ Loot }}"
+    );
+    assert_eq!(encounters.entry(51).unwrap().low, 51);
+    assert_eq!(encounters.entry(0), None);
+
+    let loot = Table::parse("loot", "a sword\n\nan [[\"apple\"]]\n").unwrap();
+    assert_eq!(loot.dice, "d2");
+    assert_eq!(loot.entries[1].high, 2);
+    let two_d6 = Table::parse(
+        "reaction",
+        "dice: 2d6\n2-5 Hostile\n6-8 Wary\n9-12 Friendly",
+    )
+    .unwrap();
+    assert_eq!(two_d6.dice, "2d6");
+
+    assert!(Table::parse("empty", "# nothing\n").is_err());
+    assert!(Table::parse("mixed", "1-2 a\nb").is_err());
+    assert!(Table::parse("overlap", "1-3 a\n3-4 b").is_err());
+    assert!(Table::parse("gap", "1-3 a\n5-6 b").is_err());
+    assert!(Table::parse("backwards", "3-1 a").is_err());
+    assert!(Table::parse("no dice", "2-5 a\n6-12 b").is_err());
+
+    // numbers that don't count up from 1 are part of the text
+    let purse = Table::parse("purse", "10 gold\n50 silver\n").unwrap();
+    assert_eq!(purse.dice, "d2");
+    assert_eq!(purse.entries[0].text, "10 gold");
+    let mixed_numbers = Table::parse("mixed numbers", "1 copper\n2 silver\na gem").unwrap();
+    assert_eq!(mixed_numbers.entries[2].text, "a gem");
+    assert_eq!(mixed_numbers.entries[0].text, "1 copper");
+    let numbered = Table::parse("numbered", "2 Wary\n1 Hostile\n3 Friendly").unwrap();
+    assert_eq!(numbered.dice, "d3");
+    assert_eq!(numbered.entry(1).unwrap().text, "Hostile");
+    assert!(Table::parse("dice gap", "dice: d6\n1 a\n3 b").is_err());
+
+    let tables = Tables::new([encounters, loot]);
+    assert_eq!(tables.names().collect::<Vec<_>>(), ["Encounters", "loot"]);
+    for seed in 0..20 {
+        let res = tables.roll("encounters", Some(seed)).await.unwrap();
+        assert_eq!(tables.roll("encounters", Some(seed)).await.unwrap(), res);
+        let n = res.roll.clone().into_i32().unwrap();
+        assert!((1..=100).contains(&n));
+        let expected: &[&str] = if n <= 50 {
+            &["2 wolves"]
+        } else if n <= 99 {
+            &["a sword", "an apple"]
+        } else {
+            &["A dragon with a sword", "A dragon with an apple"]
+        };
+        assert!(expected.contains(&res.text.as_str()), "{}", res.text);
+    }
+    assert!(tables.roll("nope", None).await.is_err());
+    let words =
+        Tables::new([Table::parse("words", "an [[\"apple\"]], [['c']] and [[ [1, 2] ]]").unwrap()]);
+    let res = words.roll("words", None).await.unwrap();
+    assert_eq!(res.text, "an apple, c and [1, 2]");
+    let hoard = Tables::new([
+        Table::parse("hoard", "[[3]] coins and {{ Gem }}").unwrap(),
+        Table::parse("gem", "a ruby").unwrap(),
+    ]);
+    let res = hoard.roll("hoard", None).await.unwrap();
+    assert_eq!(res.text, "3 coins and a ruby");
+    assert_eq!(res.roll, RRVal::from(1));
+
+    let circular = Tables::new([
+        Table::parse("a", "{{b}}").unwrap(),
+        Table::parse("b", "{{a}}").unwrap(),
+    ]);
+    assert!(circular.roll("a", None).await.is_err());
+    let unclosed = Tables::new([Table::parse("a", "[[d6").unwrap()]);
+    assert!(unclosed.roll("a", None).await.is_err());
+
+    let dir = std::env::temp_dir().join(format!("epimetheus-tables-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("names.txt"), "Ash\nBirch\n").unwrap();
+    std::fs::write(dir.join("notes.md"), "not a table").unwrap();
+    std::fs::write(dir.join("Broken.txt"), "3-1 backwards").unwrap();
+    std::fs::create_dir(dir.join("folder.txt")).unwrap();
+    let loaded = Tables::load(&dir).await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(loaded.names().collect::<Vec<_>>(), ["names"]);
+    assert!(["Ash", "Birch"].contains(&loaded.roll("Names", None).await.unwrap().text.as_str()));
+    let problems: Vec<_> = loaded.problems().collect();
+    assert_eq!(problems[0], "table Broken, line 1: backwards range 3-1");
+    assert!(problems[1].starts_with("couldn't read table folder: "));
+    assert!(
+        !problems[1].contains("epimetheus-tables"),
+        "{}",
+        problems[1]
+    );
+    assert_eq!(problems.len(), 2);
+    let err = loaded.roll("broken", None).await.unwrap_err();
+    assert_eq!(err.to_string(), "table Broken, line 1: backwards range 3-1");
+    assert_eq!(
+        Tables::load(dir.join("missing")).await.unwrap(),
+        Tables::default()
+    );
+}